@@ -0,0 +1,204 @@
+use crate::{config::Config, error::Error};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The latest versions release-monitoring.org reported, kept on disk between runs.
+///
+/// Entries older than the TTL are ignored, and so is the whole cache when bypassed. Fresh
+/// fetches are still recorded either way.
+#[derive(Debug, Default)]
+pub struct ReleaseCache {
+    path: Option<PathBuf>,
+    ttl: Duration,
+    bypass: bool,
+    entries: Mutex<HashMap<u32, CacheEntry>>,
+}
+
+/// What release-monitoring reports about a project's latest release.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatestRelease {
+    pub version: String,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// Where the release's source archive is downloaded from, when the source says so.
+    #[serde(default)]
+    pub archive_url: Option<String>,
+    /// Every version the source knows of, when it lists them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+    /// The stable ones among `versions`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stable_versions: Vec<String>,
+}
+
+/// One release-monitoring project: its latest release and when it was fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    release: LatestRelease,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+}
+
+impl ReleaseCache {
+    /// The default cache file location, `~/.cache/boulderd/release-monitoring.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache_dir| cache_dir.join("boulderd/release-monitoring.json"))
+    }
+
+    /// Load the cache from its default location with the TTL and bypass from `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self::load(
+            Self::default_path(),
+            Duration::from_secs(config.cache_ttl_minutes * 60),
+            config.no_cache,
+        )
+    }
+
+    /// Load the cache from `path`. A missing or unreadable file starts an empty cache.
+    pub fn load(path: Option<PathBuf>, ttl: Duration, bypass: bool) -> Self {
+        let entries = path
+            .as_deref()
+            .and_then(|path| read_entries(path).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            ttl,
+            bypass,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// The cached latest release of project `id`, unless it is stale or the cache is bypassed.
+    pub fn get(&self, id: u32) -> Option<LatestRelease> {
+        if self.bypass {
+            return None;
+        }
+
+        let entries = self.entries.lock().expect("Release cache poisoned");
+        let entry = entries.get(&id)?;
+        let age = now_secs().saturating_sub(entry.fetched_at);
+
+        (age < self.ttl.as_secs()).then(|| entry.release.clone())
+    }
+
+    /// Record `release` as the latest release of project `id`, fetched just now.
+    pub fn insert(&self, id: u32, release: LatestRelease) {
+        self.entries.lock().expect("Release cache poisoned").insert(
+            id,
+            CacheEntry {
+                release,
+                fetched_at: now_secs(),
+            },
+        );
+    }
+
+    /// Write the cache back to disk, creating its directory if needed.
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let io_error = |source| Error::Cache {
+            path: path.clone(),
+            source,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+
+        let entries = self.entries.lock().expect("Release cache poisoned");
+        let json = serde_json::to_string_pretty(&*entries).expect("Cache entries always serialize");
+        fs::write(path, json).map_err(io_error)
+    }
+}
+
+fn read_entries(path: &Path) -> Result<HashMap<u32, CacheEntry>, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn release(version: &str) -> LatestRelease {
+        LatestRelease {
+            version: version.to_string(),
+            homepage: Some("https://example.org".to_string()),
+            archive_url: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trips_through_disk() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("boulderd/release-monitoring.json");
+
+        let cache = ReleaseCache::load(Some(path.clone()), HOUR, false);
+        cache.insert(4242, release("1.2.3"));
+        cache.save().expect("Failed to save cache");
+
+        let reloaded = ReleaseCache::load(Some(path), HOUR, false);
+        assert_eq!(reloaded.get(4242), Some(release("1.2.3")));
+        assert_eq!(reloaded.get(1), None);
+    }
+
+    #[test]
+    fn test_stale_entries_are_ignored() {
+        let cache = ReleaseCache::load(None, HOUR, false);
+        cache
+            .entries
+            .lock()
+            .expect("Release cache poisoned")
+            .insert(
+                4242,
+                CacheEntry {
+                    release: release("1.2.3"),
+                    fetched_at: now_secs() - 2 * HOUR.as_secs(),
+                },
+            );
+
+        assert_eq!(cache.get(4242), None);
+    }
+
+    #[test]
+    fn test_bypassed_cache_still_records() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("release-monitoring.json");
+
+        let cache = ReleaseCache::load(Some(path.clone()), HOUR, true);
+        cache.insert(4242, release("1.2.3"));
+        cache.save().expect("Failed to save cache");
+
+        assert_eq!(cache.get(4242), None);
+        assert_eq!(
+            ReleaseCache::load(Some(path), HOUR, false).get(4242),
+            Some(release("1.2.3"))
+        );
+    }
+
+    #[test]
+    fn test_corrupt_cache_starts_empty() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("release-monitoring.json");
+        fs::write(&path, "not json").expect("Failed to write cache");
+
+        assert_eq!(ReleaseCache::load(Some(path), HOUR, false).get(4242), None);
+    }
+}
@@ -0,0 +1,183 @@
+use boulderd::config::{Config, OutputFormat};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Keeps AerynOS recipes up to date with their upstreams through boulder.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// What to do, defaults to `update`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The repository to track, overriding the config file.
+    #[arg(long, global = true)]
+    pub repo_path: Option<PathBuf>,
+
+    /// How many packages to process at once, overriding the config file.
+    #[arg(long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// Show the boulder commands an update would run instead of running them.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Fetch every version fresh instead of trusting the release-monitoring cache.
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Only process the packages matching this name or glob pattern, like `cosmic-*`.
+    /// Can be given several times.
+    #[arg(long, global = true)]
+    pub package: Vec<String>,
+
+    /// Don't start boulder's output lines with the package name.
+    #[arg(long, global = true)]
+    pub no_prefix: bool,
+
+    /// Exit non-zero when a package is skipped, not only when one fails.
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// How to report results, overriding the config file.
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Report outdated packages without changing anything.
+    Check,
+    /// List only the outdated packages: current and latest version and source type.
+    Outdated,
+    /// Update outdated packages through boulder.
+    Update,
+    /// Print the repository and the packages found in it.
+    Scan,
+    /// Keep running, re-scanning and updating the repository on an interval.
+    Daemon {
+        /// Minutes between update cycles, overriding the config file.
+        #[arg(long)]
+        interval: Option<u64>,
+        /// Check every package each cycle, not only the ones whose recipe changed.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl Cli {
+    /// Override the settings in `config` with the ones given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(repo_path) = &self.repo_path {
+            config.repo_path = Some(repo_path.clone());
+        }
+
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+
+        if self.dry_run {
+            config.dry_run = true;
+        }
+
+        if self.no_cache {
+            config.no_cache = true;
+        }
+
+        if self.no_prefix {
+            config.prefix_output = false;
+        }
+
+        if self.strict {
+            config.strict = true;
+        }
+
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+
+        if let Some(Command::Daemon {
+            interval: Some(interval),
+            ..
+        }) = self.command
+        {
+            config.interval_minutes = interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_config() {
+        let cli = Cli::parse_from([
+            "boulderd",
+            "check",
+            "--repo-path",
+            "/srv/recipes",
+            "--concurrency",
+            "8",
+            "--package",
+            "cosmic-applets",
+            "--package",
+            "nano",
+            "--format",
+            "json",
+        ]);
+        let mut config = Config {
+            repo_path: Some(PathBuf::from("/home/me/recipes")),
+            concurrency: 2,
+            ..Config::default()
+        };
+
+        cli.apply(&mut config);
+
+        assert_eq!(cli.command, Some(Command::Check));
+        assert_eq!(cli.package, ["cosmic-applets", "nano"]);
+        assert_eq!(config.repo_path, Some(PathBuf::from("/srv/recipes")));
+        assert_eq!(config.concurrency, 8);
+        assert_eq!(config.format, OutputFormat::Json);
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_daemon_interval_overrides_config() {
+        let cli = Cli::parse_from(["boulderd", "daemon", "--interval", "15", "--force"]);
+        let mut config = Config::default();
+
+        cli.apply(&mut config);
+
+        assert_eq!(config.interval_minutes, 15);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Daemon { force: true, .. })
+        ));
+    }
+
+    #[test]
+    fn test_unset_flags_keep_config() {
+        let cli = Cli::parse_from([
+            "boulderd",
+            "update",
+            "--dry-run",
+            "--no-cache",
+            "--strict",
+            "--no-prefix",
+        ]);
+        let mut config = Config {
+            concurrency: 2,
+            ..Config::default()
+        };
+
+        cli.apply(&mut config);
+
+        assert_eq!(config.concurrency, 2);
+        assert_eq!(config.repo_path, None);
+        assert!(config.dry_run);
+        assert!(config.no_cache);
+        assert!(config.strict);
+        assert!(!config.prefix_output);
+    }
+}
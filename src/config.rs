@@ -0,0 +1,424 @@
+use crate::{
+    error::Error,
+    repo_state::{Repo, checksum::HashAlgorithm},
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// How many packages are updated at once unless told otherwise.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How long a single request to a version or forge API may take unless told otherwise.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// How many times a failed request is retried unless told otherwise.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The least time between two API requests unless told otherwise.
+pub const DEFAULT_REQUEST_INTERVAL_MS: u64 = 250;
+
+/// How long a cached release-monitoring lookup stays fresh unless told otherwise.
+pub const DEFAULT_CACHE_TTL_MINUTES: u64 = 360;
+
+/// How often the daemon updates the repository unless told otherwise.
+pub const DEFAULT_INTERVAL_MINUTES: u64 = 60;
+
+/// Settings read from `~/.config/boulderd/config.toml`.
+///
+/// Every key is optional. Precedence, highest first: command line flags, this file, the
+/// built-in defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The repository to track, defaulting to the standard location for `repo_type`.
+    /// A leading `~/` is expanded to the home directory.
+    pub repo_path: Option<PathBuf>,
+    /// Whether the repository is the local recipes repo or the volatile repo.
+    pub repo_type: RepoType,
+    /// How many packages are updated at once.
+    pub concurrency: usize,
+    /// Show the boulder commands an update would run instead of running them.
+    pub dry_run: bool,
+    /// How many minutes the daemon waits between update cycles.
+    pub interval_minutes: u64,
+    /// How many seconds a single request to a version or forge API may take.
+    pub timeout_secs: u64,
+    /// How many times a request failing with a network error, a 5xx or a 429 is retried.
+    pub max_retries: u32,
+    /// The least time in milliseconds between two API requests, so large repos stay polite.
+    pub request_interval_ms: u64,
+    /// How many minutes a cached release-monitoring lookup is trusted before refetching.
+    pub cache_ttl_minutes: u64,
+    /// Ignore the release-monitoring cache and fetch every version fresh.
+    pub no_cache: bool,
+    /// Switch a recipe's homepage to the one release-monitoring reports during an update.
+    pub update_homepage: bool,
+    /// How `check` and `update` report their results.
+    pub format: OutputFormat,
+    /// Count skipped packages as failures when deciding the exit code.
+    pub strict: bool,
+    /// Start every line of boulder's output with the package name, e.g. `[nano] ...`.
+    pub prefix_output: bool,
+    /// How boulder is run to apply an update.
+    pub boulder: BoulderConfig,
+    /// Packages to leave alone, by name or glob pattern such as `python-*`.
+    pub ignore: Vec<String>,
+    /// More repositories tracked alongside the main one, e.g. a volatile staging repo next to
+    /// the recipes repo.
+    pub extra_repos: Vec<ExtraRepo>,
+    /// Hosts whose `.git` urls are git repositories rather than archives. GitHub and GitLab
+    /// repository urls and `git|` urls are recognized regardless.
+    pub git_forges: Vec<String>,
+    /// Never move packages to versions marked as pre-releases (`rc`, `beta`, `alpha`, `-pre`,
+    /// `.dev`), unless a recipe's monitoring file says otherwise.
+    pub stable_only: bool,
+    /// Never move packages to versions matching this regular expression, on top of a
+    /// recipe's own `ignore_version_regex`.
+    pub ignore_version_regex: Option<String>,
+    /// The digest updated archive upstreams are pinned with, unless a recipe's monitoring file
+    /// picks its own.
+    pub archive_hash: HashAlgorithm,
+    /// When not empty, the only packages to process, by name or glob pattern. `ignore` still
+    /// applies to the packages it lets through.
+    pub only: Vec<String>,
+}
+
+/// The `[boulder]` table: which boulder to run and what it does after updating a recipe.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoulderConfig {
+    /// The boulder binary, looked up in `PATH` when it is a bare name.
+    pub path: PathBuf,
+    /// Build the package once its recipe is updated.
+    pub build: bool,
+    /// Build against the local repository too.
+    pub local: bool,
+    /// More arguments appended to every `boulder recipe update`.
+    pub extra_args: Vec<String>,
+    /// How many minutes one package's boulder run may take before it is killed. Unset lets
+    /// it run for as long as it takes.
+    pub timeout_minutes: Option<u64>,
+}
+
+/// One `[[extra_repos]]` entry: a repository tracked alongside `Config::repo_path`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraRepo {
+    /// A leading `~/` is expanded to the home directory.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub repo_type: RepoType,
+}
+
+/// Which kind of repository `Config::repo_path` points at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoType {
+    #[default]
+    Local,
+    Volatile,
+}
+
+/// How results are written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Progress and a summary for people.
+    #[default]
+    Text,
+    /// One JSON array of per-package results at the end of the run, and nothing else.
+    Json,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            repo_path: None,
+            repo_type: RepoType::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            dry_run: false,
+            interval_minutes: DEFAULT_INTERVAL_MINUTES,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_interval_ms: DEFAULT_REQUEST_INTERVAL_MS,
+            cache_ttl_minutes: DEFAULT_CACHE_TTL_MINUTES,
+            no_cache: false,
+            update_homepage: false,
+            format: OutputFormat::default(),
+            strict: false,
+            prefix_output: true,
+            boulder: BoulderConfig::default(),
+            ignore: Vec::new(),
+            only: Vec::new(),
+            archive_hash: HashAlgorithm::default(),
+            extra_repos: Vec::new(),
+            stable_only: false,
+            ignore_version_regex: None,
+            git_forges: [
+                "github.com",
+                "gitlab.com",
+                "bitbucket.com",
+                "git.kernel.org",
+                "code.videolan.org",
+                "git.savannah.gnu.org",
+                "invent.kde.org",
+                "codeberg.org",
+                "git.sr.ht",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl Default for BoulderConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("boulder"),
+            build: true,
+            local: true,
+            extra_args: Vec::new(),
+            timeout_minutes: None,
+        }
+    }
+}
+
+impl Config {
+    /// The default config file location, `~/.config/boulderd/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("boulderd/config.toml"))
+    }
+
+    /// Load the config from its default location, using the built-in defaults when the file
+    /// doesn't exist.
+    pub fn load() -> Result<Self, Error> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_file(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Load the config from `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        toml::from_str(&content).map_err(|source| Error::Config {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Whether the package called `name` is on the ignore list.
+    pub fn ignores(&self, name: &str) -> bool {
+        self.ignore
+            .iter()
+            .any(|pattern| glob_matches(pattern, name))
+    }
+
+    /// The repository to track.
+    pub fn repo(&self) -> Repo {
+        let path = match &self.repo_path {
+            Some(path) => expand_home(path),
+            None => self.repo_type.default_path(),
+        };
+
+        self.repo_type.repo(path)
+    }
+
+    /// Every repository to track: the main one, then the extra ones.
+    pub fn repos(&self) -> Vec<Repo> {
+        let extra_repos = self
+            .extra_repos
+            .iter()
+            .map(|extra| extra.repo_type.repo(expand_home(&extra.path)));
+
+        [self.repo()].into_iter().chain(extra_repos).collect()
+    }
+}
+
+impl BoulderConfig {
+    /// Where the boulder binary is. A bare name is searched for in `PATH` like the shell
+    /// would.
+    pub fn locate(&self) -> Result<PathBuf, Error> {
+        let bare_name = self.path.components().count() == 1 && self.path.is_relative();
+        let found = if bare_name {
+            env::var_os("PATH").and_then(|dirs| {
+                env::split_paths(&dirs)
+                    .map(|dir| dir.join(&self.path))
+                    .find(|candidate| candidate.is_file())
+            })
+        } else {
+            Some(self.path.clone()).filter(|path| path.is_file())
+        };
+
+        found.ok_or_else(|| Error::BoulderNotFound {
+            path: self.path.clone(),
+        })
+    }
+}
+
+impl RepoType {
+    /// The repository of this kind at `path`.
+    pub fn repo(self, path: PathBuf) -> Repo {
+        match self {
+            Self::Local => Repo::Local(path),
+            Self::Volatile => Repo::Volatile(path),
+        }
+    }
+
+    /// Where this kind of repository lives when no path is configured.
+    pub fn default_path(self) -> PathBuf {
+        let home = dirs::home_dir().expect("Failed to get home directory");
+
+        match self {
+            Self::Local => home.join("repos/aerynos/recipes"),
+            Self::Volatile => home.join("repos/aerynos/volatile"),
+        }
+    }
+}
+
+/// Whether `name` matches the shell-style `pattern`, where `*` stands for any run of
+/// characters and `?` for any single one.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let regex = pattern
+        .split('*')
+        .map(|part| {
+            part.split('?')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+        .collect::<Vec<_>>()
+        .join(".*");
+
+    Regex::new(&format!("^{regex}$"))
+        .expect("Escaped glob pattern is valid")
+        .is_match(name)
+}
+
+/// Expand a leading `~/` in `path` to the home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_keys_use_defaults() {
+        let config: Config = toml::from_str("concurrency = 8").expect("Failed to parse config");
+
+        assert_eq!(config.concurrency, 8);
+        assert_eq!(config.repo_type, RepoType::Local);
+        match config.repo() {
+            Repo::Local(path) => assert_eq!(path, RepoType::Local.default_path()),
+            repo => panic!("Expected the default local repo, got {repo:?}"),
+        }
+    }
+
+    #[test]
+    fn test_config_from_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            r#"
+repo_path = "~/src/volatile"
+repo_type = "volatile"
+concurrency = 2
+"#,
+        )
+        .expect("Failed to write config");
+
+        let config = Config::from_file(&path).expect("Failed to load config");
+
+        assert_eq!(config.concurrency, 2);
+        match config.repo() {
+            Repo::Volatile(repo_path) => assert_eq!(
+                repo_path,
+                dirs::home_dir()
+                    .expect("Failed to get home directory")
+                    .join("src/volatile")
+            ),
+            repo => panic!("Expected a volatile repo, got {repo:?}"),
+        }
+    }
+
+    #[test]
+    fn test_locate_missing_boulder() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let boulder = BoulderConfig {
+            path: dir.path().join("boulder"),
+            ..BoulderConfig::default()
+        };
+
+        assert!(matches!(
+            boulder.locate(),
+            Err(Error::BoulderNotFound { path }) if path == dir.path().join("boulder")
+        ));
+
+        fs::write(dir.path().join("boulder"), "").expect("Failed to write boulder");
+        assert_eq!(
+            boulder.locate().expect("boulder should be found"),
+            dir.path().join("boulder")
+        );
+    }
+
+    #[test]
+    fn test_extra_repos() {
+        let config: Config = toml::from_str(
+            r#"
+repo_path = "/srv/recipes"
+
+[[extra_repos]]
+path = "/srv/volatile"
+repo_type = "volatile"
+"#,
+        )
+        .expect("Failed to parse config");
+
+        match &config.repos()[..] {
+            [Repo::Local(main), Repo::Volatile(extra)] => {
+                assert_eq!(main, Path::new("/srv/recipes"));
+                assert_eq!(extra, Path::new("/srv/volatile"));
+            }
+            repos => panic!("Expected a local and a volatile repo, got {repos:?}"),
+        }
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("nano", "nano"));
+        assert!(!glob_matches("nano", "nano-syntax"));
+        assert!(glob_matches("python-*", "python-requests"));
+        assert!(!glob_matches("python-*", "cpython"));
+        assert!(glob_matches("*-applets", "cosmic-applets"));
+        assert!(glob_matches("lib?", "libc"));
+        // Regex syntax in names is taken literally
+        assert!(glob_matches("gtk+3", "gtk+3"));
+        assert!(!glob_matches("gtk+3", "gtkk3"));
+    }
+
+    #[test]
+    fn test_unknown_key_is_an_error() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "concurency = 2\n").expect("Failed to write config");
+
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(Error::Config { .. })
+        ));
+    }
+}
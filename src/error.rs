@@ -0,0 +1,89 @@
+use reqwest::StatusCode;
+use std::{io, path::PathBuf};
+
+/// Errors that stop a single package from being processed.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A recipe file couldn't be read.
+    #[error("failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// A recipe file isn't valid YAML for its schema.
+    #[error("failed to parse {}: {source}", path.display())]
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// The config file isn't valid TOML for the config schema.
+    #[error("failed to parse config {}: {source}", path.display())]
+    Config {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// The release-monitoring cache couldn't be written back to disk.
+    #[error("failed to write cache {}: {source}", path.display())]
+    Cache { path: PathBuf, source: io::Error },
+    /// Talking to an upstream version or forge API failed.
+    #[error("failed to resolve the latest version: {0}")]
+    Http(#[from] ResolveError),
+    /// boulder couldn't be started or waited on.
+    #[error("failed to run boulder: {0}")]
+    Boulder(io::Error),
+    /// `--package` was given a name or pattern no package in the repository matches.
+    #[error("no packages matched {pattern}")]
+    NoPackagesMatched { pattern: String },
+    /// There is no boulder binary where the config says.
+    #[error("boulder not found at {}, install it or set boulder.path in the config", path.display())]
+    BoulderNotFound { path: PathBuf },
+}
+
+/// Why the latest upstream of a package couldn't be resolved.
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    /// The monitoring file has no release-monitoring id, and none could be found by name.
+    #[error("no release-monitoring id")]
+    NoMonitoringId,
+    /// Several release-monitoring projects share the recipe's name, so none is picked.
+    #[error("{matches} release-monitoring projects are called {name}, set releases.id")]
+    AmbiguousProject { name: String, matches: usize },
+    /// The request never got an answer (DNS, connection refused, timeout).
+    #[error("network unreachable requesting {url}, check the local network: {source}")]
+    Network { url: String, source: reqwest::Error },
+    /// The server answered with a 4xx/5xx status.
+    #[error("server error {status} from {url}, check the recipe's monitoring data")]
+    Server { url: String, status: StatusCode },
+    /// A request kept failing with a network error, a 5xx or a 429 until the retries ran out.
+    #[error("gave up on {url} after retrying, try again later: {last}")]
+    RetriesExhausted {
+        url: String,
+        #[source]
+        last: Box<ResolveError>,
+    },
+    /// The server answered, but not with what we expected.
+    #[error("invalid response from {url}: {source}")]
+    InvalidResponse { url: String, source: reqwest::Error },
+    /// The server answered, but not with the JSON we expected.
+    #[error("invalid response from {url}: {source}")]
+    InvalidJson {
+        url: String,
+        source: serde_json::Error,
+    },
+    /// A downloaded archive is empty or not an archive at all.
+    #[error("download of {url} looks wrong: {reason}")]
+    BadDownload { url: String, reason: String },
+    /// The release feed has no entry with a version in its title.
+    #[error("no release version found in the feed at {url}")]
+    NoFeedVersion { url: String },
+    /// Every upstream version the backend offered is ruled out by the package's version
+    /// filters.
+    #[error("upstream version {version} is filtered out and there is no other to pick")]
+    NoAllowedVersion { version: String },
+    /// An `ignore_version_regex` isn't a valid regular expression.
+    #[error("invalid ignore_version_regex {pattern}: {source}")]
+    InvalidVersionRegex {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// The new version was found, but not its hash or url.
+    #[error("no hash or url available")]
+    NoHashOrUrl,
+}
@@ -0,0 +1,24 @@
+//! Keeps AerynOS recipes up to date with their upstreams through boulder.
+//!
+//! [`RepoState`] scans a recipes repository into [`Package`]s, and [`WorkspaceState`] several
+//! repositories at once. Each package can be planned ([`Package::plan_update`]), checked
+//! ([`Package::is_outdated`]) or updated ([`Package::update`]) against its upstream, with
+//! requests going through an [`HttpGet`] such as [`HttpClient`]. [`logic`] runs those over
+//! every repository the way the `boulderd` binary does.
+//!
+//! Recipe and monitoring file schemas stay internal, only what is read from them is exposed.
+
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod logic;
+pub mod repo_state;
+pub mod shutdown;
+
+pub use config::{BoulderConfig, Config, ExtraRepo, OutputFormat, RepoType};
+pub use error::{Error, ResolveError};
+pub use repo_state::{
+    Download, HttpClient, HttpGet, LoadedPackage, OutdatedPackage, Package, PackageReport,
+    PendingUpdate, Repo, RepoState, UpdateOutcome, UpdatePlan, VersionSourceType, WorkspaceState,
+    checksum::HashAlgorithm,
+};
@@ -1,16 +1,711 @@
-use crate::repo_state::RepoState;
-use std::thread;
+use crate::{
+    cache::ReleaseCache,
+    config::{Config, OutputFormat, glob_matches},
+    error::Error,
+    repo_state::{
+        HttpClient, HttpGet, OutdatedPackage, Package, PackageReport, Repo, UpdateOutcome,
+        UpdatePlan, WorkspaceState,
+    },
+    shutdown::Shutdown,
+};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
 
-pub fn update_cosmic_packages() {
-    let local_state = RepoState::new_local();
+/// Scan the configured repositories, keeping only the packages on `config.only` when it is
+/// set, and only the packages matching one of the globs `packages` when any are given. A
+/// pattern in `packages` matching nothing is an error.
+pub fn load_state(config: &Config, packages: &[String]) -> Result<WorkspaceState, Error> {
+    let mut state = WorkspaceState::from_config(config);
+    if !config.only.is_empty() {
+        state.retain_matching(&config.only);
+    }
+    if !packages.is_empty() {
+        // A typo next to a pattern that does match would otherwise go unnoticed
+        if let Some(pattern) = packages.iter().find(|pattern| {
+            !state
+                .packages()
+                .any(|package| glob_matches(pattern, &package.name()))
+        }) {
+            return Err(Error::NoPackagesMatched {
+                pattern: pattern.clone(),
+            });
+        }
+        state.retain_matching(packages);
+    }
+
+    Ok(state)
+}
 
-    local_state.packages.into_iter().for_each(|package| {
-        thread::scope(|s| {
-            s.spawn(move || {
-                if let Err(e) = package.update() {
+/// Re-scan and update the repository every `config.interval_minutes` until `shutdown` is
+/// requested.
+///
+/// Cycles only look at packages whose recipe changed since the previous cycle, except for a
+/// full cycle every `config.cache_ttl_minutes`, once cached releases are stale anyway. With
+/// `force` every cycle is a full one.
+pub fn run_daemon(config: &Config, packages: &[String], force: bool, shutdown: &Shutdown) {
+    let interval = Duration::from_secs(config.interval_minutes.max(1) * 60);
+    let full_cycle_every = Duration::from_secs(config.cache_ttl_minutes * 60);
+    let mut last_cycle: Option<Instant> = None;
+    let mut last_full_cycle: Option<Instant> = None;
+
+    loop {
+        // A cycle that blows up is reported, the next one gets a fresh start
+        let cycle = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut state = match load_state(config, packages) {
+                Ok(state) => state,
+                Err(e) => {
                     eprintln!("{e}");
+                    return Instant::now();
                 }
-            });
+            };
+            let started = state.last_update;
+
+            let full = force
+                || last_full_cycle
+                    .is_none_or(|last| started.duration_since(last) >= full_cycle_every);
+            if full {
+                last_full_cycle = Some(started);
+            } else if let Some(last) = last_cycle {
+                let scanned = state.packages().count();
+                state.retain_changed_since(last);
+                eprintln!(
+                    "{} unchanged recipes left alone this cycle",
+                    scanned - state.packages().count()
+                );
+            }
+
+            update_packages(&mut state, config, shutdown);
+            started
+        }));
+
+        let started = match cycle {
+            Ok(started) => {
+                last_cycle = Some(started);
+                started
+            }
+            Err(_) => {
+                eprintln!(
+                    "Update cycle failed, retrying in {} minutes",
+                    interval.as_secs() / 60
+                );
+                Instant::now()
+            }
+        };
+
+        if shutdown.wait(next_cycle_delay(started, interval, Instant::now())) {
+            break;
+        }
+    }
+}
+
+/// How long to sleep so the next cycle starts `interval` after the one started at `started`.
+fn next_cycle_delay(started: Instant, interval: Duration, now: Instant) -> Duration {
+    (started + interval).saturating_duration_since(now)
+}
+
+/// Update every package of every repository, or only show what would be run when
+/// `config.dry_run` is set.
+/// Packages `state` already updated are reported up to date without being looked at again.
+///
+/// Once `shutdown` is requested the packages already updating finish and the rest are left
+/// alone. Returns a report of every package that was looked at, by package name.
+pub fn update_packages(
+    state: &mut WorkspaceState,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> Vec<PackageReport> {
+    let client = http_client(config);
+    let reports = update_each(state, &client, config, shutdown);
+    save_release_cache(&client);
+
+    match config.format {
+        OutputFormat::Text => print!("{}", summary(&reports)),
+        OutputFormat::Json => println!("{}", json_report(&reports)),
+    }
+
+    reports
+}
+
+/// The concurrent part of `update_packages`, with requests going through `client`.
+fn update_each(
+    state: &mut WorkspaceState,
+    client: &(dyn HttpGet + Sync),
+    config: &Config,
+    shutdown: &Shutdown,
+) -> Vec<PackageReport> {
+    let reports = Mutex::new(Vec::new());
+
+    let packages = state.packages_mut().collect();
+    for_each_concurrent(packages, config.concurrency, |package: &mut Package| {
+        if shutdown.is_requested() {
+            return;
+        }
+
+        // A bad recipe is logged and the rest of the packages carry on
+        let outcome = package.update(client, config).unwrap_or_else(|e| {
+            eprintln!("Failed to update {}: {e}", package.path.display());
+            UpdateOutcome::Failed {
+                reason: e.to_string(),
+            }
         });
+
+        reports
+            .lock()
+            .expect("Reports poisoned")
+            .push(package.report(outcome, config));
     });
+
+    sorted(reports)
+}
+
+/// A rollup of an update run: how many packages ended up each way, then the details of
+/// every package that didn't simply stay up to date.
+fn summary(reports: &[PackageReport]) -> String {
+    let mut updated = Vec::new();
+    let mut planned = Vec::new();
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    let mut up_to_date = 0;
+
+    for PackageReport { name, outcome, .. } in reports {
+        match outcome {
+            UpdateOutcome::UpToDate => up_to_date += 1,
+            UpdateOutcome::Updated { from, to } => updated.push(format!("{name}: {from} -> {to}")),
+            UpdateOutcome::WouldUpdate(pending) => planned.push(format!(
+                "{name}: {} -> {}",
+                pending.current_version, pending.latest_version
+            )),
+            UpdateOutcome::Skipped { reason } => skipped.push(format!("{name}: {reason}")),
+            UpdateOutcome::Failed { reason } => failed.push(format!("{name}: {reason}")),
+        }
+    }
+
+    let mut counts = vec![
+        format!("{up_to_date} up to date"),
+        format!("{} updated", updated.len()),
+    ];
+    if !planned.is_empty() {
+        counts.push(format!("{} would update", planned.len()));
+    }
+    counts.push(format!("{} skipped", skipped.len()));
+    counts.push(format!("{} failed", failed.len()));
+
+    let mut report = format!("Summary: {}\n", counts.join(", "));
+    for (heading, lines) in [
+        ("Updated", updated),
+        ("Would update", planned),
+        ("Skipped", skipped),
+        ("Failed", failed),
+    ] {
+        if !lines.is_empty() {
+            report.push_str(&format!("{heading}:\n"));
+            for line in lines {
+                report.push_str(&format!("  {line}\n"));
+            }
+        }
+    }
+
+    report
+}
+
+/// Report every package with a newer upstream, without changing anything.
+pub fn check_packages(state: WorkspaceState, config: &Config) -> Vec<PackageReport> {
+    let client = http_client(config);
+    let reports = Mutex::new(Vec::new());
+
+    for_each_concurrent(state.into_packages(), config.concurrency, |package| {
+        let outcome = match package.plan_update(&client, config) {
+            Ok(UpdatePlan::Pending(pending)) => {
+                if config.format == OutputFormat::Text {
+                    println!(
+                        "{}: {} -> {}",
+                        package.name(),
+                        pending.current_version,
+                        pending.latest_version
+                    );
+                }
+                UpdateOutcome::WouldUpdate(pending)
+            }
+            Ok(UpdatePlan::UpToDate) => UpdateOutcome::UpToDate,
+            Ok(UpdatePlan::Skipped { reason }) => UpdateOutcome::Skipped { reason },
+            Err(e) => {
+                eprintln!("Failed to check {}: {e}", package.path.display());
+                UpdateOutcome::Failed {
+                    reason: e.to_string(),
+                }
+            }
+        };
+
+        reports
+            .lock()
+            .expect("Reports poisoned")
+            .push(package.report(outcome, config));
+    });
+
+    save_release_cache(&client);
+
+    let reports = sorted(reports);
+    if config.format == OutputFormat::Json {
+        println!("{}", json_report(&reports));
+    }
+
+    reports
+}
+
+/// List the packages whose upstream moved past their recipe, without changing anything.
+pub fn list_outdated(state: &WorkspaceState, config: &Config) -> Vec<OutdatedPackage> {
+    let client = http_client(config);
+    let outdated = state.outdated(&client, config);
+    save_release_cache(&client);
+
+    match config.format {
+        OutputFormat::Text => {
+            for package in &outdated {
+                println!(
+                    "{}: {} -> {} ({})",
+                    package.name,
+                    package.current_version,
+                    package.latest_version,
+                    package.source_type
+                );
+            }
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&outdated).expect("Outdated packages always serialize")
+        ),
+    }
+
+    outdated
+}
+
+/// Whether a run with these `reports` should exit non-zero: a package failed, or was skipped
+/// under `strict`. Packages on the `ignore` list are skipped on purpose and never count.
+pub fn run_failed(reports: &[PackageReport], strict: bool) -> bool {
+    reports.iter().any(|report| match &report.outcome {
+        UpdateOutcome::Failed { .. } => true,
+        outcome @ UpdateOutcome::Skipped { .. } => strict && !outcome.is_ignored(),
+        _ => false,
+    })
+}
+
+/// The reports collected by a run's workers, ordered by package name.
+fn sorted(reports: Mutex<Vec<PackageReport>>) -> Vec<PackageReport> {
+    let mut reports = reports.into_inner().expect("Reports poisoned");
+    reports.sort_by(|a, b| a.name.cmp(&b.name));
+    reports
+}
+
+/// `reports` as a pretty-printed JSON array, for `--format json`.
+fn json_report(reports: &[PackageReport]) -> String {
+    serde_json::to_string_pretty(reports).expect("Package reports always serialize")
+}
+
+/// Print every repository and the packages discovered in it.
+pub fn scan_packages(state: &WorkspaceState) {
+    for repo in &state.repos {
+        let (kind, path) = match &repo.repo_type {
+            Repo::Local(path) => ("Local", path),
+            Repo::Volatile(path) => ("Volatile", path),
+        };
+
+        println!(
+            "{kind} repository at {} ({} packages)",
+            path.display(),
+            repo.packages.len()
+        );
+        for package in &repo.packages {
+            println!("  {}: {}", package.name(), package.path.display());
+        }
+    }
+}
+
+/// The HTTP client for one run, answering release-monitoring lookups from the disk cache.
+fn http_client(config: &Config) -> HttpClient {
+    HttpClient::new(config).with_release_cache(ReleaseCache::from_config(config))
+}
+
+/// Keep this run's release-monitoring lookups for the next one.
+fn save_release_cache(client: &HttpClient) {
+    if let Err(e) = client.release_cache().save() {
+        eprintln!("{e}");
+    }
+}
+
+/// Run `task` on every item using at most `concurrency` threads, returning once all are done.
+fn for_each_concurrent<T, F>(items: Vec<T>, concurrency: usize, task: F)
+where
+    T: Send,
+    F: Fn(T) + Sync,
+{
+    let workers = concurrency.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter());
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                // Each worker keeps pulling the next item until the queue runs dry. The lock
+                // is released at the end of the `let` so tasks don't run under it.
+                loop {
+                    let Some(item) = queue.lock().expect("Work queue poisoned").next() else {
+                        break;
+                    };
+                    task(item);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::LatestRelease,
+        config::BoulderConfig,
+        error::ResolveError,
+        repo_state::{Download, IGNORED, VersionSourceType},
+    };
+    use std::{
+        fs,
+        io::Write,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Serves releases from its cache and any archive as its own url, with no other requests
+    /// answered.
+    struct CachedReleases {
+        cache: ReleaseCache,
+    }
+
+    impl HttpGet for CachedReleases {
+        fn get_text(&self, _url: &str) -> Result<String, ResolveError> {
+            Err(ResolveError::NoHashOrUrl)
+        }
+
+        fn download(&self, url: &str, sink: &mut dyn Write) -> Result<Download, ResolveError> {
+            sink.write_all(url.as_bytes())
+                .expect("Writing to a hasher doesn't fail");
+            Ok(Download {
+                len: url.len() as u64,
+                content_type: None,
+            })
+        }
+
+        fn release_cache(&self) -> &ReleaseCache {
+            &self.cache
+        }
+    }
+
+    fn package_names(state: &WorkspaceState) -> Vec<String> {
+        let mut names: Vec<_> = state
+            .packages()
+            .map(|package| package.name().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_load_state_selects_packages() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        for package in ["c/cosmic-applets", "c/cosmic-term", "n/nano"] {
+            let dir = repo.path().join(package);
+            fs::create_dir_all(&dir).expect("Failed to create package dir");
+            fs::write(dir.join("stone.yaml"), "name: pkg\nversion: 1.0.0\n")
+                .expect("Failed to write stone.yaml");
+            fs::write(dir.join("monitoring.yaml"), "releases:\n    id: 1\n")
+                .expect("Failed to write monitoring.yaml");
+        }
+        let config = Config {
+            repo_path: Some(repo.path().to_path_buf()),
+            ..Config::default()
+        };
+
+        let patterns =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        let exact = load_state(&config, &patterns(&["nano"])).expect("nano should match");
+        let wildcard =
+            load_state(&config, &patterns(&["cosmic-*"])).expect("cosmic-* should match");
+        let repeated = load_state(&config, &patterns(&["cosmic-term", "nano"]))
+            .expect("Both patterns should match");
+        let everything = load_state(&config, &[]).expect("Unfiltered state should load");
+
+        assert_eq!(package_names(&exact), ["nano"]);
+        assert_eq!(package_names(&wildcard), ["cosmic-applets", "cosmic-term"]);
+        assert_eq!(package_names(&repeated), ["cosmic-term", "nano"]);
+        assert_eq!(everything.packages().count(), 3);
+        assert!(matches!(
+            load_state(&config, &patterns(&["cosmic"])),
+            Err(Error::NoPackagesMatched { pattern }) if pattern == "cosmic"
+        ));
+        assert!(matches!(
+            load_state(&config, &patterns(&["nano", "cosmic"])),
+            Err(Error::NoPackagesMatched { pattern }) if pattern == "cosmic"
+        ));
+    }
+
+    #[test]
+    fn test_next_cycle_delay() {
+        let started = Instant::now();
+        let interval = Duration::from_secs(60);
+
+        assert_eq!(
+            next_cycle_delay(started, interval, started + Duration::from_secs(20)),
+            Duration::from_secs(40)
+        );
+        // A cycle that overran its interval starts the next one straight away
+        assert_eq!(
+            next_cycle_delay(started, interval, started + Duration::from_secs(90)),
+            Duration::ZERO
+        );
+    }
+
+    fn report(name: &str, outcome: UpdateOutcome) -> PackageReport {
+        PackageReport {
+            name: name.to_string(),
+            current_version: Some("1.0.0".to_string()),
+            latest_version: None,
+            source_type: Some(VersionSourceType::Archive),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let reports = [
+            report("bar", UpdateOutcome::UpToDate),
+            report(
+                "baz",
+                UpdateOutcome::Failed {
+                    reason: "boulder exited with exit status: 1".to_string(),
+                },
+            ),
+            report(
+                "foo",
+                UpdateOutcome::Updated {
+                    from: "1.0.0".to_string(),
+                    to: "1.1.0".to_string(),
+                },
+            ),
+            report("qux", UpdateOutcome::UpToDate),
+            report(
+                "quux",
+                UpdateOutcome::Skipped {
+                    reason: "no release-monitoring id".to_string(),
+                },
+            ),
+        ];
+
+        assert_eq!(
+            summary(&reports),
+            "\
+Summary: 2 up to date, 1 updated, 1 skipped, 1 failed
+Updated:
+  foo: 1.0.0 -> 1.1.0
+Skipped:
+  quux: no release-monitoring id
+Failed:
+  baz: boulder exited with exit status: 1
+"
+        );
+        assert_eq!(
+            summary(&[]),
+            "Summary: 0 up to date, 0 updated, 0 skipped, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn test_run_failed() {
+        let skipped = report(
+            "foo",
+            UpdateOutcome::Skipped {
+                reason: "no release-monitoring id".to_string(),
+            },
+        );
+        let failed = report(
+            "bar",
+            UpdateOutcome::Failed {
+                reason: "boulder exited with exit status: 1".to_string(),
+            },
+        );
+        let up_to_date = report("baz", UpdateOutcome::UpToDate);
+
+        assert!(!run_failed(&[report("qux", UpdateOutcome::UpToDate)], true));
+        assert!(!run_failed(&[up_to_date.clone(), skipped.clone()], false));
+        assert!(run_failed(&[up_to_date.clone(), skipped], true));
+        assert!(run_failed(&[up_to_date, failed], false));
+    }
+
+    #[test]
+    fn test_ignored_packages_pass_strict_runs() {
+        let ignored = report(
+            "foo",
+            UpdateOutcome::Skipped {
+                reason: IGNORED.to_string(),
+            },
+        );
+
+        assert!(!run_failed(
+            &[ignored.clone(), report("bar", UpdateOutcome::UpToDate)],
+            true
+        ));
+        assert!(!run_failed(&[ignored], false));
+    }
+
+    #[test]
+    fn test_json_report_round_trips() {
+        let reports = [
+            PackageReport {
+                latest_version: Some("1.1.0".to_string()),
+                ..report(
+                    "foo",
+                    UpdateOutcome::Updated {
+                        from: "1.0.0".to_string(),
+                        to: "1.1.0".to_string(),
+                    },
+                )
+            },
+            PackageReport {
+                source_type: None,
+                ..report(
+                    "bar",
+                    UpdateOutcome::Skipped {
+                        reason: "no valid upstream".to_string(),
+                    },
+                )
+            },
+        ];
+
+        let json: serde_json::Value =
+            serde_json::from_str(&json_report(&reports)).expect("Report should be valid JSON");
+
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "name": "foo",
+                    "current_version": "1.0.0",
+                    "latest_version": "1.1.0",
+                    "source_type": "archive",
+                    "outcome": {"status": "updated", "from": "1.0.0", "to": "1.1.0"}
+                },
+                {
+                    "name": "bar",
+                    "current_version": "1.0.0",
+                    "latest_version": null,
+                    "source_type": null,
+                    "outcome": {"status": "skipped", "reason": "no valid upstream"}
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_concurrent_updates_keep_to_their_own_recipe() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        let cache = ReleaseCache::load(None, Duration::from_secs(60), false);
+        for (id, name, current, latest) in
+            [(1, "foo", "1.0.0", "1.1.0"), (2, "bar", "2.0.0", "2.5.0")]
+        {
+            let dir = repo.path().join(name);
+            fs::create_dir_all(&dir).expect("Failed to create package dir");
+            fs::write(
+                dir.join("stone.yaml"),
+                format!("name: {name}\nversion: {current}\nupstreams:\n    - https://example.org/{name}-{current}.tar.xz: 0123abcd\n"),
+            )
+            .expect("Failed to write stone.yaml");
+            fs::write(
+                dir.join("monitoring.yaml"),
+                format!("releases:\n    id: {id}\n"),
+            )
+            .expect("Failed to write monitoring.yaml");
+            cache.insert(
+                id,
+                LatestRelease {
+                    version: latest.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        // Stand-in for boulder: moves the stone.yaml it runs next to to the `--ver` version,
+        // slowly enough for both updates to overlap
+        let boulder = repo.path().join("boulder");
+        fs::write(
+            &boulder,
+            r#"#!/bin/sh
+sleep 0.2
+sed -i "s/^version: .*/version: $4/; s/-[0-9.]*\.tar\.xz/-$4.tar.xz/" stone.yaml
+"#,
+        )
+        .expect("Failed to write stub boulder");
+        fs::set_permissions(
+            &boulder,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .expect("Failed to make stub boulder executable");
+        let config = Config {
+            concurrency: 2,
+            boulder: BoulderConfig {
+                path: boulder,
+                ..BoulderConfig::default()
+            },
+            ..Config::default()
+        };
+        let mut state = WorkspaceState::new(vec![Repo::Local(repo.path().to_path_buf())]);
+
+        let reports = update_each(
+            &mut state,
+            &CachedReleases { cache },
+            &config,
+            &Shutdown::new(),
+        );
+        let version = |name: &str| {
+            fs::read_to_string(repo.path().join(name).join("stone.yaml"))
+                .expect("Failed to read stone.yaml")
+                .lines()
+                .find_map(|line| line.strip_prefix("version: ").map(String::from))
+        };
+
+        assert!(
+            reports
+                .iter()
+                .all(|report| matches!(report.outcome, UpdateOutcome::Updated { .. })),
+            "{reports:?}"
+        );
+        assert_eq!(version("foo").as_deref(), Some("1.1.0"));
+        assert_eq!(version("bar").as_deref(), Some("2.5.0"));
+    }
+
+    #[test]
+    fn test_for_each_concurrent_caps_live_threads() {
+        let live = AtomicUsize::new(0);
+        let max_live = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+
+        let start = Instant::now();
+        for_each_concurrent((0..8).collect(), 3, |_: i32| {
+            let now_live = live.fetch_add(1, Ordering::SeqCst) + 1;
+            max_live.fetch_max(now_live, Ordering::SeqCst);
+
+            thread::sleep(Duration::from_millis(100));
+
+            live.fetch_sub(1, Ordering::SeqCst);
+            done.fetch_add(1, Ordering::SeqCst);
+        });
+        let elapsed = start.elapsed();
+
+        assert_eq!(done.load(Ordering::SeqCst), 8);
+        assert_eq!(max_live.load(Ordering::SeqCst), 3);
+        // Sequentially this would take 800ms, three at a time takes three rounds
+        assert!(
+            elapsed < Duration::from_millis(700),
+            "Tasks did not run concurrently, took {elapsed:?}"
+        );
+    }
 }
@@ -1,8 +1,81 @@
-pub mod logic;
-pub mod repo_state;
+mod cli;
 
-use logic::update_cosmic_packages;
+use boulderd::{
+    Config,
+    logic::{
+        check_packages, list_outdated, load_state, run_daemon, run_failed, scan_packages,
+        update_packages,
+    },
+    shutdown::Shutdown,
+};
+use clap::Parser;
+use cli::{Cli, Command};
+use std::process;
 
 fn main() {
-    update_cosmic_packages();
+    let cli = Cli::parse();
+
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    cli.apply(&mut config);
+
+    let packages = &cli.package;
+    let load_state = || {
+        load_state(&config, packages).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        })
+    };
+
+    match cli.command.unwrap_or(Command::Update) {
+        Command::Check => {
+            let reports = check_packages(load_state(), &config);
+            if run_failed(&reports, config.strict) {
+                process::exit(1);
+            }
+        }
+        Command::Update => {
+            require_boulder(&config);
+            let mut state = load_state();
+            let reports = update_packages(&mut state, &config, &Shutdown::new());
+            if run_failed(&reports, config.strict) {
+                process::exit(1);
+            }
+        }
+        Command::Outdated => {
+            list_outdated(&load_state(), &config);
+        }
+        Command::Scan => scan_packages(&load_state()),
+        Command::Daemon { force, .. } => {
+            let shutdown = Shutdown::on_signals().unwrap_or_else(|e| {
+                eprintln!("Failed to install signal handlers: {e}");
+                process::exit(1);
+            });
+
+            require_boulder(&config);
+            // A pattern matching nothing now would match nothing every cycle
+            if !packages.is_empty() {
+                load_state();
+            }
+            run_daemon(&config, packages, force, &shutdown);
+        }
+    }
+}
+
+/// Exit right away when an update would need boulder and it isn't installed, instead of
+/// failing every package.
+fn require_boulder(config: &Config) {
+    if config.dry_run {
+        return;
+    }
+
+    if let Err(e) = config.boulder.locate() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
 }
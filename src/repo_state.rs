@@ -1,15 +1,60 @@
-use dirs;
+use crate::{
+    cache::{LatestRelease, ReleaseCache},
+    config::{BoulderConfig, Config, OutputFormat, RepoType, glob_matches},
+    error::{Error, ResolveError},
+};
+use backend::{
+    CRATES_IO_API, CratesIo, GitHubReleases, PYPI_API, PyPI, RELEASE_MONITORING_API,
+    ReleaseMonitoring, RssFeed, VersionBackend, VersionFilter,
+};
+use checksum::{HashAlgorithm, archive_checksum};
 use jwalk::WalkDir;
-use serde::Deserialize;
+use regex::Regex;
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header::{CONTENT_TYPE, RETRY_AFTER},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::{
-    collections::HashMap,
-    fs,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-    process::{Command, Stdio},
-    time::Instant,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
+pub mod backend;
+pub mod checksum;
+
+/// How much of boulder's stderr is kept for the error report of a failed update.
+const STDERR_TAIL_BYTES: usize = 64 * 1024;
+
+/// How often a boulder run with a timeout is checked on.
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+/// How long downloading an archive to hash it may take, since archives can be large.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Base url of the GitHub REST API.
+const GITHUB_API: &str = "https://api.github.com";
+
+/// How long the first retry of a failed request waits, doubling with every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Several repositories tracked together, such as a recipes repo and a volatile staging repo,
+/// scanned and updated as one.
+#[derive(Debug, Clone)]
+pub struct WorkspaceState {
+    pub repos: Vec<RepoState>,
+    pub last_update: Instant,
+}
+
 /// RepoState holds the state of the repositories the system is supposed to be tracking.
 #[derive(Debug, Clone)]
 pub struct RepoState {
@@ -18,13 +63,184 @@ pub struct RepoState {
     pub last_update: Instant,
 }
 
+/// Where version lookups fetch their urls from, so they can be answered without a network.
+pub trait HttpGet {
+    /// The body of a successful GET of `url`.
+    fn get_text(&self, url: &str) -> Result<String, ResolveError>;
+
+    /// Stream the body of a successful GET of `url` into `sink`.
+    fn download(&self, url: &str, sink: &mut dyn Write) -> Result<Download, ResolveError>;
+
+    /// The cache release-monitoring lookups are answered from and recorded in.
+    fn release_cache(&self) -> &ReleaseCache;
+}
+
+/// What a download wrote, besides the body itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    /// How many bytes the body had.
+    pub len: u64,
+    /// The `Content-Type` the server gave the body, if any.
+    pub content_type: Option<String>,
+}
+
+/// The HTTP client shared by every package of a run, so connections are pooled across them.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    max_retries: u32,
+    backoff: Duration,
+    min_interval: Duration,
+    last_request: Arc<Mutex<Option<Instant>>>,
+    release_cache: Arc<ReleaseCache>,
+}
+
 /// Package holds the path to the package, its manifest, and monitoring file.
 #[derive(Debug, Clone)]
 pub struct Package {
     pub path: PathBuf,
     pub manifest: PathBuf,
     pub monitoring: PathBuf,
+    /// Set once boulder has updated the recipe, so later updates of this package are no-ops.
     pub updated: bool,
+    /// What `load` last parsed, reused until either file changes.
+    loaded: Arc<Mutex<Option<Arc<LoadedPackage>>>>,
+}
+
+/// A package's `stone.yaml` and `monitoring.yaml`, parsed once by `Package::load`.
+#[derive(Debug)]
+pub struct LoadedPackage {
+    manifest: Manifest,
+    monitoring: Monitoring,
+    /// The modification time and length of both files when they were read.
+    stamps: [Option<(SystemTime, u64)>; 2],
+}
+
+impl LoadedPackage {
+    /// The version the recipe is at.
+    pub fn version(&self) -> &str {
+        &self.manifest.version
+    }
+}
+
+/// What happened when a package was checked for updates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UpdateOutcome {
+    /// The recipe already tracks the latest upstream.
+    UpToDate,
+    /// boulder updated the recipe from version `from` to `to`.
+    Updated { from: String, to: String },
+    /// There was nothing to resolve the package against.
+    Skipped { reason: String },
+    /// boulder ran but didn't manage to update the recipe.
+    Failed { reason: String },
+    /// `dry_run` is set, so the update was only reported and boulder never ran.
+    WouldUpdate(PendingUpdate),
+}
+
+/// The `Skipped` reason of a package on the config's `ignore` list.
+pub const IGNORED: &str = "ignored";
+
+impl UpdateOutcome {
+    /// Whether the package was skipped for being on the config's `ignore` list, which is on
+    /// purpose rather than a problem.
+    pub fn is_ignored(&self) -> bool {
+        matches!(self, Self::Skipped { reason } if reason == IGNORED)
+    }
+}
+
+/// The work `Package::update` would do, worked out without touching the recipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdatePlan {
+    /// The recipe already tracks the latest upstream.
+    UpToDate,
+    /// There was nothing to resolve the package against.
+    Skipped { reason: String },
+    /// The recipe needs moving to a newer upstream.
+    Pending(PendingUpdate),
+}
+
+/// What came of one package in a `check` or `update` run, as reported to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageReport {
+    pub name: String,
+    /// The recipe's version before the run, unless its manifest couldn't be read.
+    pub current_version: Option<String>,
+    /// The upstream version, when it was found.
+    pub latest_version: Option<String>,
+    /// How the recipe's first upstream is pinned.
+    pub source_type: Option<VersionSourceType>,
+    pub outcome: UpdateOutcome,
+}
+
+/// A package whose upstream has moved past its recipe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutdatedPackage {
+    pub name: String,
+    pub current_version: String,
+    pub latest_version: String,
+    /// How the recipe's upstream is pinned.
+    pub source_type: VersionSourceType,
+}
+
+/// A recipe update waiting to be handed to boulder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingUpdate {
+    pub current_version: String,
+    pub latest_version: String,
+    pub current_release: Option<u32>,
+    /// The recipe's release after the update: 1 for a new version, bumped for a new hash.
+    pub release: u32,
+    pub current_homepage: Option<String>,
+    /// The homepage to switch the recipe to, when upstream moved and `update_homepage` is on.
+    pub homepage: Option<String>,
+    /// One argument per upstream of the recipe, in the recipe's order.
+    #[serde(skip)]
+    upstream_args: Vec<UpstreamArg>,
+    /// The digest the archives the update moves to are pinned with.
+    #[serde(skip)]
+    hash_algorithm: HashAlgorithm,
+}
+
+impl PendingUpdate {
+    /// The arguments boulder is run with to apply this update, as `boulder` asks for.
+    pub fn boulder_args(&self, boulder: &BoulderConfig) -> Vec<String> {
+        let mut args = ["recipe", "update", "--ver", &self.latest_version]
+            .map(String::from)
+            .to_vec();
+        // boulder matches the upstreams to the recipe's by position
+        for upstream_arg in &self.upstream_args {
+            args.push("--upstream".to_string());
+            args.push(upstream_arg.to_string());
+        }
+        args.extend(["stone.yaml", "-w"].map(String::from));
+
+        if boulder.build {
+            args.push("--build".to_string());
+        }
+        if boulder.local {
+            args.push("--local".to_string());
+        }
+        args.extend(boulder.extra_args.iter().cloned());
+
+        args
+    }
+
+    /// The archive urls the update moves the recipe's upstreams to.
+    fn archive_urls(&self) -> impl Iterator<Item = &str> {
+        self.upstream_args
+            .iter()
+            .filter_map(|upstream_arg| match upstream_arg {
+                UpstreamArg::Archive { url } => Some(url.as_str()),
+                UpstreamArg::Git { .. } => None,
+            })
+    }
+
+    /// How the recipe's first upstream, which the version follows, is pinned.
+    fn source_type(&self) -> VersionSourceType {
+        self.upstream_args[0].source_type()
+    }
 }
 
 /// The type of repository and the path to the repository that needs to be tracked.
@@ -38,106 +254,21 @@ pub enum Repo {
 impl Default for RepoState {
     fn default() -> Self {
         // The current default is the local repository.
-        let local_repo = dirs::home_dir()
-            .expect("Failed to get home directory")
-            .join("repos/aerynos/recipes");
-
-        // Get all of the packages that the repo holds.
-        let packages = WalkDir::new(&local_repo.to_string_lossy().to_string())
-            .min_depth(1)
-            .max_depth(2)
-            .into_iter()
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                if entry.file_type().is_dir() {
-                    let path = entry.path().to_path_buf();
-                    let manifest = path.join("stone.yaml");
-                    let monitoring = path.join("monitoring.yaml");
-
-                    // Only include if both manifest and monitoring files exist
-                    if manifest.exists() && monitoring.exists() {
-                        Some(Package {
-                            path,
-                            manifest,
-                            monitoring,
-                            updated: false,
-                        })
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<Package>>();
+        let local_repo = RepoType::Local.default_path();
 
-        // Return the RepoState of the default repo.
-        Self {
-            repo_type: Repo::Local(PathBuf::from(
-                local_repo
-                    .to_str()
-                    .take()
-                    .expect("Failed to get local repo path"),
-            )),
-            packages,
-            last_update: Instant::now(),
-        }
+        // Return the RepoState of the default repo with all of the packages it holds.
+        Self::new(Repo::Local(local_repo))
     }
 }
 
 impl RepoState {
     pub fn new(repo_type: Repo) -> Self {
-        match &repo_type {
-            Repo::Local(local_path) => {
-                let packages: Vec<Package> = WalkDir::new(local_path.as_path())
-                    .into_iter()
-                    .filter(|entry| entry.is_ok())
-                    .map(|entry| {
-                        let entry = entry.unwrap();
-                        let path = entry.path().to_path_buf();
-                        let manifest = path.join("stone.yaml");
-                        let monitoring = path.join("monitoring.yaml");
-
-                        Package {
-                            path,
-                            manifest,
-                            monitoring,
-                            updated: false,
-                        }
-                    })
-                    .collect();
-
-                Self {
-                    repo_type,
-                    packages,
-                    last_update: Instant::now(),
-                }
-            }
-            Repo::Volatile(vol_path) => {
-                let packages: Vec<Package> = WalkDir::new(vol_path.as_path())
-                    .into_iter()
-                    .filter(|entry| entry.is_ok())
-                    .map(|entry| {
-                        let entry = entry.unwrap();
-                        let path = PathBuf::from(entry.file_name());
-                        let manifest = path.join("stone.yaml");
-                        let monitoring = path.join("monitoring.yaml");
-
-                        Package {
-                            path,
-                            manifest,
-                            monitoring,
-                            updated: false,
-                        }
-                    })
-                    .collect();
+        let packages = Self::packages_iter(&repo_type).collect();
 
-                Self {
-                    repo_type,
-                    packages,
-                    last_update: Instant::now(),
-                }
-            }
+        Self {
+            repo_type,
+            packages,
+            last_update: Instant::now(),
         }
     }
 
@@ -145,542 +276,4068 @@ impl RepoState {
         Self::default()
     }
 
+    /// The packages of `repo`, yielded as the walk finds them instead of once it is over, so
+    /// work on the first ones can start while a large repository is still being walked.
+    /// `new` collects this into `packages`.
+    pub fn packages_iter(repo: &Repo) -> impl Iterator<Item = Package> + use<> {
+        match repo {
+            Repo::Local(path) | Repo::Volatile(path) => discover_packages(path),
+        }
+    }
+
     pub fn new_volatile() -> Self {
-        let vol_path = PathBuf::new(); // TODO: Make this the path to the volatile repo
+        Self::new(Repo::Volatile(RepoType::Volatile.default_path()))
+    }
+
+    /// The RepoState of the local repository at `path`.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Self::new(Repo::Local(path.into()))
+    }
 
+    /// Keep only the packages whose `stone.yaml` or `monitoring.yaml` changed after `since`.
+    pub fn retain_changed_since(&mut self, since: Instant) {
+        // File times are wall clock times, so `since` is moved onto the wall clock
+        let since = SystemTime::now() - since.elapsed();
+        self.packages.retain(|package| package.changed_since(since));
+    }
+
+    /// Keep only the packages whose name matches one of the glob `patterns`.
+    pub fn retain_matching(&mut self, patterns: &[String]) {
+        self.packages.retain(|package| {
+            patterns
+                .iter()
+                .any(|pattern| glob_matches(pattern, &package.name()))
+        });
+    }
+
+    /// The RepoState of the repository chosen by `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.repo())
+    }
+
+    /// Every package whose upstream moved past its recipe, without running boulder. Packages
+    /// that can't be checked are logged and left out.
+    pub fn outdated(&self, client: &dyn HttpGet, config: &Config) -> Vec<OutdatedPackage> {
+        self.packages
+            .iter()
+            .filter_map(|package| {
+                package.is_outdated(client, config).unwrap_or_else(|e| {
+                    eprintln!("Failed to check {}: {e}", package.path.display());
+                    None
+                })
+            })
+            .collect()
+    }
+}
+
+impl WorkspaceState {
+    /// Scan every repository of `repos`.
+    pub fn new(repos: Vec<Repo>) -> Self {
         Self {
-            repo_type: Repo::Volatile(vol_path),
-            packages: Vec::new(),
+            repos: repos.into_iter().map(RepoState::new).collect(),
             last_update: Instant::now(),
         }
     }
-}
 
-impl Package {
-    pub fn update(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if !self.updated {
-            let monitoring_content =
-                fs::read_to_string(&format!("{}", self.monitoring.to_str().unwrap())).unwrap();
-            let manifest_content =
-                fs::read_to_string(&format!("{}", self.manifest.to_str().unwrap())).unwrap();
-
-            let monitoring: Monitoring = serde_yaml::from_str(&monitoring_content).unwrap();
-            let manifest: Manifest = serde_yaml::from_str(&manifest_content).unwrap();
-
-            let cur_vers = manifest.version;
-            let cur_hash = manifest
-                .upstreams
-                .as_ref()
-                .and_then(|upstreams| upstreams.first())
-                .map(|entry| match &entry.value {
-                    UpstreamValue::Simple(hash) | UpstreamValue::Git(hash) => hash.clone(),
-                    UpstreamValue::Extended { hash, .. } => hash.clone(),
-                })
-                .unwrap_or_default();
+    /// The WorkspaceState of the repositories chosen by `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.repos())
+    }
 
-            let (latest_vers, latest_hash_or_url) = if let Some(first_upstream) = manifest
-                .upstreams
-                .as_ref()
-                .and_then(|upstream| upstream.first())
-            {
-                self.get_latest(&monitoring, first_upstream, &cur_vers)
-            } else {
-                (String::new(), String::new())
-            };
+    /// The packages of every repository, repository by repository.
+    pub fn packages(&self) -> impl Iterator<Item = &Package> {
+        self.repos.iter().flat_map(|repo| &repo.packages)
+    }
 
-            // Early return if we couldn't get a latest_hash_or_url value
-            if latest_hash_or_url.is_empty() {
-                eprintln!(
-                    "Skipping {} - no hash or url available",
-                    self.path.file_name().unwrap().to_string_lossy().to_string()
-                );
-                return Ok(());
-            }
+    /// `packages`, mutably.
+    pub fn packages_mut(&mut self) -> impl Iterator<Item = &mut Package> {
+        self.repos.iter_mut().flat_map(|repo| &mut repo.packages)
+    }
 
-            // Compare the current version, current hash, latest version, latest hash
-            if latest_vers != cur_vers
-                || serde_yaml::Value::String(latest_hash_or_url.clone()) != cur_hash
-            {
-                let upstream_arg = manifest
-                    .upstreams
-                    .as_ref()
-                    .and_then(|upstreams| upstreams.first())
-                    .map(|entry| match get_version_source_type(entry) {
-                        VersionSourceType::Git => format!("{}, {latest_hash_or_url}", entry.url),
-                        VersionSourceType::Archive => latest_hash_or_url.clone(),
-                    })
-                    .filter(|arg| !arg.is_empty())
-                    .unwrap_or_else(|| {
-                        eprintln!(
-                            "Failed update for {} - no valid upstream",
-                            self.path.display()
-                        );
-                        String::new()
-                    });
-
-                if upstream_arg.is_empty() {
-                    return Ok(());
-                }
+    /// The packages of every repository, taken out of the workspace.
+    pub fn into_packages(self) -> Vec<Package> {
+        self.repos
+            .into_iter()
+            .flat_map(|repo| repo.packages)
+            .collect()
+    }
 
-                let mut boulder_cmd = Command::new("boulder")
-                    .args([
-                        "recipe",
-                        "update",
-                        "--ver",
-                        &format!("{latest_vers}"),
-                        "--upstream",
-                        &upstream_arg,
-                        "stone.yaml",
-                        "-w",
-                        "--build",
-                        "--local",
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .expect("Failed to run boulder recipe update command");
-
-                let stdout = boulder_cmd
-                    .stdout
-                    .take()
-                    .expect("Failed to open recipe update process stdout");
-                let stdout_reader = BufReader::new(stdout);
-
-                // Stream the recipe update command output to the terminal
-                stdout_reader.lines().for_each(|line| {
-                    if let Ok(line) = line {
-                        println!("{line}");
-                    }
-                });
+    /// Keep only the packages whose name matches one of the glob `patterns`, in every
+    /// repository.
+    pub fn retain_matching(&mut self, patterns: &[String]) {
+        for repo in &mut self.repos {
+            repo.retain_matching(patterns);
+        }
+    }
 
-                let update_status = boulder_cmd
-                    .wait_with_output()
-                    .expect("Failed to wait for boulder to complete");
+    /// Keep only the packages whose `stone.yaml` or `monitoring.yaml` changed after `since`,
+    /// in every repository.
+    pub fn retain_changed_since(&mut self, since: Instant) {
+        for repo in &mut self.repos {
+            repo.retain_changed_since(since);
+        }
+    }
 
-                if update_status.status.success() {
-                    println!("Successfully updated package for repository");
-                } else {
-                    eprintln!(
-                        "Update failed for: {}",
-                        self.path
-                            .file_name()
-                            .expect("Failed to get package directory file name")
-                            .to_string_lossy()
-                            .to_string()
-                    );
+    /// Every outdated package of every repository, as `RepoState::outdated` finds them.
+    pub fn outdated(&self, client: &dyn HttpGet, config: &Config) -> Vec<OutdatedPackage> {
+        self.repos
+            .iter()
+            .flat_map(|repo| repo.outdated(client, config))
+            .collect()
+    }
+}
 
-                    // Print the error from boulder's stderr
-                    if !update_status.stderr.is_empty() {
-                        let err = String::from_utf8_lossy(&update_status.stderr);
-                        eprintln!("Error output: {err}");
-                    }
-                }
-            } else {
-                println!(
-                    "Nothing to update for {}",
-                    self.path
-                        .file_name()
-                        .expect("Failed to get package directory file name")
-                        .to_string_lossy()
-                        .to_string()
-                );
-            }
-        }
+impl HttpClient {
+    /// A client using the timeout and retry settings from `config`.
+    pub fn new(config: &Config) -> Self {
+        let client = Client::builder()
+            // crates.io turns away clients that don't say who they are and how to reach them
+            .user_agent(concat!(
+                "boulderd/",
+                env!("CARGO_PKG_VERSION"),
+                " (https://github.com/bhh32/boulderd)"
+            ))
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .expect("Failed to build the HTTP client");
 
-        Ok(())
+        Self {
+            client,
+            max_retries: config.max_retries,
+            backoff: RETRY_BACKOFF,
+            min_interval: Duration::from_millis(config.request_interval_ms),
+            last_request: Arc::default(),
+            release_cache: Arc::default(),
+        }
     }
 
-    fn get_latest(
-        &self,
-        monitoring: &Monitoring,
-        cur_upstream: &UpstreamEntry,
-        cur_vers: &str,
-    ) -> (String, String) {
-        let client = reqwest::blocking::Client::new();
+    /// Answer release-monitoring lookups from `cache` where it has a fresh entry.
+    pub fn with_release_cache(self, cache: ReleaseCache) -> Self {
+        Self {
+            release_cache: Arc::new(cache),
+            ..self
+        }
+    }
 
-        // Get version from release-monitoring.org if available
-        if let Some(id) = monitoring.releases.id {
-            let url = format!("https://release-monitoring.org/api/project/{id}");
+    /// GET `url`, retrying network failures, 5xx and 429 responses with exponential backoff.
+    /// A 429 waits as long as its `Retry-After` header asks for instead, when it has one. A
+    /// failure still there once the retries run out is `ResolveError::RetriesExhausted`.
+    pub fn get(&self, url: &str) -> Result<Response, ResolveError> {
+        self.get_within(url, None)
+    }
 
-            if let Ok(response) = client.get(&url).send() {
-                if let Ok(project) = response.json::<ApiProject>() {
-                    let new_version = project.version;
+    /// `get`, with `timeout` replacing the configured timeout when given.
+    fn get_within(&self, url: &str, timeout: Option<Duration>) -> Result<Response, ResolveError> {
+        let mut retries = 0;
 
-                    // If version unchanged, return current
-                    if new_version == cur_vers {
-                        return (new_version, self.get_current_hash(cur_upstream));
-                    }
+        loop {
+            self.wait_turn();
+            let mut request = self.client.get(url);
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            let sent = request.send();
 
-                    // Get hash/URL based on source type
-                    match get_version_source_type(cur_upstream) {
-                        VersionSourceType::Git => (
-                            new_version.clone(),
-                            self.get_git_hash(&new_version, cur_upstream),
-                        ),
-                        VersionSourceType::Archive => {
-                            let new_url =
-                                replace_version_in_url(&cur_upstream.url, cur_vers, &new_version);
-                            (new_version, new_url)
-                        }
-                    }
-                } else {
-                    (String::new(), String::new())
+            let retry_after = sent.as_ref().ok().and_then(retry_after);
+            let result = check_response(sent, url);
+            let transient = match &result {
+                Err(ResolveError::Network { .. }) => true,
+                Err(ResolveError::Server { status, .. }) => {
+                    status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
                 }
-            } else {
-                (String::new(), String::new())
+                _ => false,
+            };
+
+            if !transient {
+                return result;
             }
-        } else {
-            // No monitoring ID - skip for now
-            (String::new(), String::new())
+            if let Err(last) = result
+                && retries == self.max_retries
+            {
+                eprintln!("Warning: giving up on {url} after {retries} retries");
+                return Err(ResolveError::RetriesExhausted {
+                    url: url.to_string(),
+                    last: Box::new(last),
+                });
+            }
+
+            let delay = retry_after.unwrap_or(self.backoff * 2u32.pow(retries));
+            eprintln!("Request to {url} failed, retrying in {delay:?}");
+            thread::sleep(delay);
+            retries += 1;
         }
     }
 
-    fn get_current_hash(&self, upstream: &UpstreamEntry) -> String {
-        match &upstream.value {
-            UpstreamValue::Simple(hash) | UpstreamValue::Git(hash) => hash.clone(),
-            UpstreamValue::Extended { hash, .. } => hash.clone(),
+    /// Block until `min_interval` has passed since the previous request, from any thread.
+    fn wait_turn(&self) {
+        // The lock is held while sleeping so waiting requests go out one interval apart
+        let mut last_request = self.last_request.lock().expect("Request pacing poisoned");
+        if let Some(last) = *last_request {
+            thread::sleep((last + self.min_interval).saturating_duration_since(Instant::now()));
         }
+
+        *last_request = Some(Instant::now());
     }
+}
 
-    fn get_git_hash(&self, version: &str, upstream: &UpstreamEntry) -> String {
-        #[derive(Debug, serde::Deserialize)]
-        struct GitHubTag {
-            name: String,
-            commit: GitHubCommit,
-        }
+impl HttpGet for HttpClient {
+    fn get_text(&self, url: &str) -> Result<String, ResolveError> {
+        self.get(url)?
+            .text()
+            .map_err(|source| ResolveError::InvalidResponse {
+                url: url.to_string(),
+                source,
+            })
+    }
 
-        #[derive(Debug, serde::Deserialize)]
-        struct GitHubCommit {
-            sha: String,
-        }
+    fn download(&self, url: &str, sink: &mut dyn Write) -> Result<Download, ResolveError> {
+        let mut response = self.get_within(url, Some(DOWNLOAD_TIMEOUT))?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
 
-        let client = reqwest::blocking::Client::new();
-        let clean_url = upstream.url.trim_start_matches("git|");
+        let len = response
+            .copy_to(sink)
+            .map_err(|source| ResolveError::InvalidResponse {
+                url: url.to_string(),
+                source,
+            })?;
 
-        if !clean_url.contains("github.com") {
-            eprintln!("Non-GitHub git source for {}", self.path.display());
-            // TODO: Add a path for other git forges
-            return String::new();
+        Ok(Download { len, content_type })
+    }
+
+    /// The cache of release-monitoring lookups, to be saved once a run is done.
+    fn release_cache(&self) -> &ReleaseCache {
+        &self.release_cache
+    }
+}
+
+impl Package {
+    /// The package in the directory `path`, made of its `stone.yaml` and `monitoring.yaml`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        Self {
+            manifest: path.join("stone.yaml"),
+            monitoring: path.join("monitoring.yaml"),
+            path,
+            updated: false,
+            loaded: Arc::default(),
         }
+    }
 
-        // Extract owner/repo from GitHub URL
-        let parts: Vec<&str> = clean_url.split("/").collect();
-        if parts.len() < 5 {
-            eprintln!("Invalide GitHub URL format: {clean_url}");
-            return String::new();
+    /// Parse the recipe and monitoring files. The result is kept and handed out again until
+    /// either file's modification time or length changes, so planning, checking and
+    /// reporting on a package read its files once between them.
+    pub fn load(&self) -> Result<Arc<LoadedPackage>, Error> {
+        let stamps = [&self.manifest, &self.monitoring].map(|path| {
+            let metadata = fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        });
+
+        let mut loaded = self.loaded.lock().expect("Loaded package poisoned");
+        if let Some(cached) = loaded.as_ref()
+            && stamps.iter().all(Option::is_some)
+            && cached.stamps == stamps
+        {
+            return Ok(Arc::clone(cached));
         }
 
-        let owner = parts[3];
-        let repo = parts[4].trim_end_matches(".git");
+        let fresh = Arc::new(LoadedPackage {
+            monitoring: read_yaml(&self.monitoring)?,
+            manifest: read_yaml(&self.manifest)?,
+            stamps,
+        });
+        *loaded = Some(Arc::clone(&fresh));
 
-        // Get tags from GitHub API
-        let github_api_url = format!("https://api.github.com/repos/{owner}/{repo}/tags");
+        Ok(fresh)
+    }
 
-        match client
-            .get(&github_api_url)
-            .header("User-Agent", "boulderd/0.1.0")
-            .send()
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    if let Ok(tags) = response.json::<Vec<GitHubTag>>() {
-                        // Try to extract the version match
-                        for tag in &tags {
-                            if tag.name == version || tag.name == format!("v{version}") {
-                                return tag.commit.sha.clone();
-                            }
-                        }
+    pub fn update(
+        &mut self,
+        client: &dyn HttpGet,
+        config: &Config,
+    ) -> Result<UpdateOutcome, Error> {
+        if self.updated {
+            return Ok(UpdateOutcome::UpToDate);
+        }
 
-                        // If no exact match, return the latest tag's commit
-                        if let Some(latest_tag) = tags.first() {
-                            return latest_tag.commit.sha.clone();
-                        }
-                    }
-                } else {
-                    eprintln!(
-                        "GitHub API request failed with status: {}",
-                        response.status()
+        // JSON output is printed as a whole at the end of the run
+        let text = config.format == OutputFormat::Text;
+
+        match self.plan_update(client, config)? {
+            UpdatePlan::UpToDate => {
+                if text {
+                    println!("Nothing to update for {}", self.name());
+                }
+                Ok(UpdateOutcome::UpToDate)
+            }
+            UpdatePlan::Skipped { reason } => Ok(UpdateOutcome::Skipped { reason }),
+            UpdatePlan::Pending(pending) if config.dry_run => {
+                if text {
+                    println!(
+                        "Would update {} to release {}: {} {}",
+                        self.name(),
+                        pending.release,
+                        config.boulder.path.display(),
+                        pending.boulder_args(&config.boulder).join(" ")
                     );
                 }
+                Ok(UpdateOutcome::WouldUpdate(pending))
+            }
+            UpdatePlan::Pending(pending) => {
+                let outcome = self.run_boulder(client, &pending, config)?;
+                self.updated = matches!(outcome, UpdateOutcome::Updated { .. });
+                Ok(outcome)
             }
-            Err(e) => eprintln!("GitHub API error for {}: {e}", self.path.display()),
         }
-
-        // Return String::new() if fails
-        String::new()
     }
-}
 
-// Helper Functions
-fn is_git_source(upstream_entry: &UpstreamEntry) -> bool {
-    // Check if explicitly marked as git
-    if upstream_entry.url.starts_with("git|") {
-        return true;
+    /// What makes this package outdated, if it is, compared exactly as `update` would but
+    /// without running boulder.
+    pub fn is_outdated(
+        &self,
+        client: &dyn HttpGet,
+        config: &Config,
+    ) -> Result<Option<OutdatedPackage>, Error> {
+        Ok(match self.plan_update(client, config)? {
+            UpdatePlan::Pending(pending) => Some(OutdatedPackage {
+                name: self.name().into_owned(),
+                source_type: pending.source_type(),
+                current_version: pending.current_version,
+                latest_version: pending.latest_version,
+            }),
+            UpdatePlan::UpToDate | UpdatePlan::Skipped { .. } => None,
+        })
     }
 
-    // Check for git forges
-    let git_forges = [
-        "github.com",
-        "gitlab.com",
-        "bitbucket.com",
-        "git.kernel.org",
-        "code.videolan.org",
-        "git.savannah.gnu.org",
-        "invent.kde.org",
-    ];
+    /// Report `outcome` of this package's run along with what its recipe says.
+    pub fn report(&self, outcome: UpdateOutcome, config: &Config) -> PackageReport {
+        let loaded = self.load().ok();
+        let manifest = loaded.as_ref().map(|loaded| &loaded.manifest);
+        let source_type = manifest
+            .and_then(|manifest| manifest.upstreams.as_ref()?.first())
+            .map(|upstream| get_version_source_type(upstream, &config.git_forges));
 
-    git_forges
-        .iter()
-        .any(|forge| upstream_entry.url.contains(forge))
-        && upstream_entry.url.contains(".git")
-}
+        // After an update the recipe already has the new version
+        let (current_version, latest_version) = match &outcome {
+            UpdateOutcome::Updated { from, to } => (Some(from.clone()), Some(to.clone())),
+            UpdateOutcome::WouldUpdate(pending) => (
+                Some(pending.current_version.clone()),
+                Some(pending.latest_version.clone()),
+            ),
+            UpdateOutcome::UpToDate => {
+                let version = manifest.map(|manifest| manifest.version.clone());
+                (version.clone(), version)
+            }
+            UpdateOutcome::Skipped { .. } | UpdateOutcome::Failed { .. } => {
+                (manifest.map(|manifest| manifest.version.clone()), None)
+            }
+        };
 
-fn get_version_source_type(upstream_entry: &UpstreamEntry) -> VersionSourceType {
-    if is_git_source(upstream_entry) {
-        VersionSourceType::Git
-    } else {
-        VersionSourceType::Archive
+        PackageReport {
+            name: self.name().into_owned(),
+            current_version,
+            latest_version,
+            source_type,
+            outcome,
+        }
     }
-}
 
-fn replace_version_in_url(url: &str, old_version: &str, new_version: &str) -> String {
-    use regex::Regex;
+    /// Work out whether the package needs updating and how, without changing anything.
+    pub fn plan_update(&self, client: &dyn HttpGet, config: &Config) -> Result<UpdatePlan, Error> {
+        if config.ignores(&self.name()) {
+            return Ok(self.skip(IGNORED.to_string()));
+        }
 
-    let patterns = [
-        format!(r"v?{}", regex::escape(old_version)),
-        format!(r"{}", regex::escape(old_version)),
-    ];
+        let loaded = self.load()?;
+        let LoadedPackage {
+            manifest,
+            monitoring,
+            ..
+        } = &*loaded;
+
+        let cur_vers = manifest.version.clone();
+        let Some(first_upstream) = manifest
+            .upstreams
+            .as_ref()
+            .and_then(|upstreams| upstreams.first())
+        else {
+            return Ok(self.skip("no valid upstream".to_string()));
+        };
+        let cur_hash = self.get_current_hash(first_upstream);
+
+        // Early return if there is nothing to resolve against, failed requests are errors
+        let latest = match self.get_latest(
+            client,
+            monitoring,
+            &manifest.name,
+            first_upstream,
+            &cur_vers,
+            config,
+        ) {
+            Ok(latest) => latest,
+            Err(
+                e @ (ResolveError::NoMonitoringId
+                | ResolveError::AmbiguousProject { .. }
+                | ResolveError::NoFeedVersion { .. }
+                | ResolveError::NoAllowedVersion { .. }
+                | ResolveError::RetriesExhausted { .. }
+                | ResolveError::NoHashOrUrl),
+            ) => return Ok(self.skip(e.to_string())),
+            Err(e) => return Err(e.into()),
+        };
 
-    for pattern in &patterns {
-        if let Ok(reg) = Regex::new(pattern) {
-            if reg.is_match(url) {
-                return reg.replace(url, new_version).to_string();
+        // Compare the current version, current hash, latest version, latest hash.
+        // A recipe without a hash yet never matches, so it always gets one filled in.
+        let hash_changed = latest.detail.hash() != Some(cur_hash.as_str());
+
+        let newer = match compare_versions(&cur_vers, &latest.version) {
+            Some(Ordering::Greater) => true,
+            Some(Ordering::Equal) => false,
+            Some(Ordering::Less) => {
+                return Ok(self.skip(format!(
+                    "upstream {} is older than {cur_vers}",
+                    latest.version
+                )));
+            }
+            None => {
+                eprintln!(
+                    "Warning: can't order versions {cur_vers} and {} of {}, treating them as different",
+                    latest.version,
+                    self.name()
+                );
+                true
             }
+        };
+
+        if !newer && !hash_changed {
+            return Ok(UpdatePlan::UpToDate);
         }
+
+        // Maintainers may have picked the homepage on purpose, so it only follows upstream on request
+        let homepage = latest.homepage.filter(|homepage| {
+            config.update_homepage && manifest.homepage.as_ref() != Some(homepage)
+        });
+
+        // Further upstreams (data archives, submodules, ...) move along with the version
+        let mut upstream_args = vec![UpstreamArg::new(first_upstream, &latest.detail)];
+        upstream_args.extend(manifest.upstreams.iter().flatten().skip(1).map(|upstream| {
+            self.secondary_upstream_arg(client, upstream, &latest.version, &cur_vers, config)
+        }));
+
+        Ok(UpdatePlan::Pending(PendingUpdate {
+            upstream_args,
+            hash_algorithm: monitoring
+                .releases
+                .archive_hash
+                .unwrap_or(config.archive_hash),
+            current_version: cur_vers,
+            latest_version: latest.version,
+            current_release: manifest.release,
+            release: next_release(manifest.release, newer),
+            current_homepage: manifest.homepage.clone(),
+            homepage,
+        }))
     }
 
-    // Fallback in the case a pattern isn't found
-    url.replace(old_version, new_version)
-}
+    /// Where a secondary `upstream` of the recipe points at `version`. One whose url or tags
+    /// don't follow the main version is kept as it is.
+    fn secondary_upstream_arg(
+        &self,
+        client: &dyn HttpGet,
+        upstream: &UpstreamEntry,
+        version: &str,
+        cur_vers: &str,
+        config: &Config,
+    ) -> UpstreamArg {
+        let release = LatestRelease {
+            version: version.to_string(),
+            homepage: None,
+            archive_url: None,
+            ..Default::default()
+        };
 
-// Helper Structs and enums
-#[derive(Debug, serde::Deserialize)]
-enum VersionSourceType {
-    Git,
-    Archive,
-}
+        let detail = match self.resolve_upstream(client, release, upstream, cur_vers, config) {
+            Some(resolved) => resolved.detail,
+            None => match get_version_source_type(upstream, &config.git_forges) {
+                VersionSourceType::Git => UpstreamDetail::GitHash(self.get_current_hash(upstream)),
+                VersionSourceType::Archive => UpstreamDetail::ArchiveUrl {
+                    url: upstream.url.clone(),
+                    hash: None,
+                },
+            },
+        };
 
-#[derive(Debug, serde::Deserialize)]
-struct Releases {
-    id: Option<u32>,
-    #[serde(default)]
-    rss: Option<String>,
-}
+        UpstreamArg::new(upstream, &detail)
+    }
 
-#[derive(Debug, serde::Deserialize)]
-struct Monitoring {
-    releases: Releases,
-    #[serde(default)]
-    security: Option<serde_yaml::Value>,
+    /// Hand `pending` to boulder. Its output goes to stdout only when text output is on.
+    ///
+    /// The archives the update moves to are downloaded and hashed first, and once boulder is
+    /// done the recipe is pinned to those checksums.
+    fn run_boulder(
+        &self,
+        client: &dyn HttpGet,
+        pending: &PendingUpdate,
+        config: &Config,
+    ) -> Result<UpdateOutcome, Error> {
+        let text = config.format == OutputFormat::Text;
+        // Concurrent updates interleave their output, the prefix tells them apart
+        let prefix = if config.prefix_output {
+            format!("[{}] ", self.name())
+        } else {
+            String::new()
+        };
+
+        // A broken download stops the update before the recipe is touched
+        let checksums = pending
+            .archive_urls()
+            .map(|url| Ok((url, archive_checksum(client, url, pending.hash_algorithm)?)))
+            .collect::<Result<Vec<_>, ResolveError>>()?;
+
+        write_recipe_metadata(
+            &self.manifest,
+            Some(pending.release),
+            pending.homepage.as_deref(),
+        )?;
+
+        // boulder edits the `stone.yaml` of the directory it runs in, so it runs in the
+        // package's. A relative boulder path is still taken from where boulderd was started.
+        let program = if config.boulder.path.components().count() > 1 {
+            std::path::absolute(&config.boulder.path).unwrap_or(config.boulder.path.clone())
+        } else {
+            config.boulder.path.clone()
+        };
+        let spawned = Command::new(program)
+            .args(pending.boulder_args(&config.boulder))
+            .current_dir(&self.path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let boulder_cmd = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                // boulder never touched the recipe, so it goes back to how it was
+                self.restore_recipe_metadata(pending)?;
+
+                return Err(match e.kind() {
+                    io::ErrorKind::NotFound => Error::BoulderNotFound {
+                        path: config.boulder.path.clone(),
+                    },
+                    _ => Error::Boulder(e),
+                });
+            }
+        };
+
+        let timeout = config
+            .boulder
+            .timeout_minutes
+            .map(|minutes| Duration::from_secs(minutes * 60));
+        let Some(update_status) =
+            wait_streaming(boulder_cmd, STDERR_TAIL_BYTES, timeout, !text, &prefix)
+                .map_err(Error::Boulder)?
+        else {
+            eprintln!("Update of {} timed out, boulder was killed", self.name());
+            self.restore_recipe_metadata(pending)?;
+
+            return Ok(UpdateOutcome::Failed {
+                reason: format!(
+                    "timeout after {} minutes",
+                    config.boulder.timeout_minutes.unwrap_or_default()
+                ),
+            });
+        };
+
+        if update_status.status.success() {
+            write_upstream_checksums(&self.manifest, &checksums)?;
+            if text {
+                println!("Successfully updated package for repository");
+            }
+            Ok(UpdateOutcome::Updated {
+                from: pending.current_version.clone(),
+                to: pending.latest_version.clone(),
+            })
+        } else {
+            eprintln!("Update failed for: {}", self.name());
+
+            // The next run bumps from the old release again
+            self.restore_recipe_metadata(pending)?;
+
+            // boulder's stderr was already shown as it ran, its last line usually says why
+            let stderr = String::from_utf8_lossy(&update_status.stderr);
+            let reason = match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+                Some(last_line) => {
+                    format!(
+                        "boulder exited with {}: {}",
+                        update_status.status,
+                        last_line.trim()
+                    )
+                }
+                None => format!("boulder exited with {}", update_status.status),
+            };
+
+            Ok(UpdateOutcome::Failed { reason })
+        }
+    }
+
+    /// Put back the release and homepage the recipe had before `pending` was started.
+    fn restore_recipe_metadata(&self, pending: &PendingUpdate) -> Result<(), Error> {
+        write_recipe_metadata(
+            &self.manifest,
+            pending.current_release,
+            pending.current_homepage.as_deref(),
+        )
+    }
+
+    /// Whether the recipe or monitoring file was modified after `time`. Files whose
+    /// modification time can't be read count as changed.
+    pub fn changed_since(&self, time: SystemTime) -> bool {
+        [&self.manifest, &self.monitoring].iter().any(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .is_none_or(|modified| modified > time)
+        })
+    }
+
+    /// Give up on this package for `reason`, letting the user know why.
+    fn skip(&self, reason: String) -> UpdatePlan {
+        eprintln!("Skipping {} - {reason}", self.name());
+        UpdatePlan::Skipped { reason }
+    }
+
+    /// The package name, i.e. its directory name, lossily converted for display.
+    pub fn name(&self) -> Cow<'_, str> {
+        self.path
+            .file_name()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+    }
+
+    fn get_latest(
+        &self,
+        client: &dyn HttpGet,
+        monitoring: &Monitoring,
+        name: &str,
+        cur_upstream: &UpstreamEntry,
+        cur_vers: &str,
+        config: &Config,
+    ) -> Result<ResolvedUpstream, ResolveError> {
+        // Rolling recipes follow a branch tip rather than tagged releases
+        if monitoring.releases.track_branch {
+            return self.get_branch_head(
+                client,
+                GITHUB_API,
+                monitoring.releases.branch.as_deref(),
+                cur_upstream,
+                config,
+            );
+        }
+
+        let release = self.latest_release(client, monitoring, name, cur_upstream, config)?;
+
+        self.resolve_upstream(client, release, cur_upstream, cur_vers, config)
+            .ok_or(ResolveError::NoHashOrUrl)
+    }
+
+    /// The newest release the package's backend offers that its version filters let through.
+    /// Backends that list several versions pick among those that pass, the others' one version
+    /// has to pass itself.
+    fn latest_release(
+        &self,
+        client: &dyn HttpGet,
+        monitoring: &Monitoring,
+        name: &str,
+        upstream: &UpstreamEntry,
+        config: &Config,
+    ) -> Result<LatestRelease, ResolveError> {
+        let filter = monitoring.releases.version_filter(config)?;
+        let release = monitoring
+            .releases
+            .backend(name, filter.clone())
+            .latest(client, self, upstream)?;
+
+        if filter.allows(&release.version) {
+            Ok(release)
+        } else {
+            Err(ResolveError::NoAllowedVersion {
+                version: release.version,
+            })
+        }
+    }
+
+    /// The newest upstream version of this package, looked up the same way `update` does but
+    /// without comparing it to the recipe or resolving its hash. `None` when the package has
+    /// nothing to look it up by, or its lookup kept failing until the retries ran out.
+    pub fn latest_version(
+        &self,
+        client: &dyn HttpGet,
+        config: &Config,
+    ) -> Result<Option<String>, Error> {
+        let loaded = self.load()?;
+        let LoadedPackage {
+            manifest,
+            monitoring,
+            ..
+        } = &*loaded;
+        let Some(first_upstream) = manifest
+            .upstreams
+            .as_ref()
+            .and_then(|upstreams| upstreams.first())
+        else {
+            return Ok(None);
+        };
+
+        let latest = if monitoring.releases.track_branch {
+            self.get_branch_head(
+                client,
+                GITHUB_API,
+                monitoring.releases.branch.as_deref(),
+                first_upstream,
+                config,
+            )
+            .map(|resolved| resolved.version)
+        } else {
+            self.latest_release(client, monitoring, &manifest.name, first_upstream, config)
+                .map(|release| release.version)
+        };
+
+        match latest {
+            Ok(version) => Ok(Some(version)),
+            Err(
+                ResolveError::NoMonitoringId
+                | ResolveError::AmbiguousProject { .. }
+                | ResolveError::NoFeedVersion { .. }
+                | ResolveError::NoAllowedVersion { .. }
+                | ResolveError::RetriesExhausted { .. }
+                | ResolveError::NoHashOrUrl,
+            ) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Resolve where `upstream` points at the version of `release`, based on its source type.
+    fn resolve_upstream(
+        &self,
+        client: &dyn HttpGet,
+        release: LatestRelease,
+        upstream: &UpstreamEntry,
+        cur_vers: &str,
+        config: &Config,
+    ) -> Option<ResolvedUpstream> {
+        let new_version = release.version;
+
+        // If version unchanged and the recipe already has a hash, keep the current one.
+        // An empty hash means the recipe is freshly authored and still needs one.
+        let cur_hash = self.get_current_hash(upstream);
+        let unchanged = compare_versions(cur_vers, &new_version) == Some(Ordering::Equal)
+            && !cur_hash.is_empty();
+
+        let detail = match get_version_source_type(upstream, &config.git_forges) {
+            VersionSourceType::Git => {
+                let hash = if unchanged {
+                    cur_hash
+                } else {
+                    self.get_git_hash(client, &new_version, upstream)
+                };
+
+                if hash.is_empty() {
+                    return None;
+                }
+
+                UpstreamDetail::GitHash(hash)
+            }
+            VersionSourceType::Archive => {
+                if unchanged {
+                    UpstreamDetail::ArchiveUrl {
+                        url: upstream.url.clone(),
+                        hash: Some(cur_hash),
+                    }
+                } else {
+                    // A backend that knows the release's archive beats guessing it from the url
+                    let url = match release.archive_url {
+                        Some(url) => url,
+                        None => replace_version_in_url(&upstream.url, cur_vers, &new_version)?,
+                    };
+
+                    UpstreamDetail::ArchiveUrl { url, hash: None }
+                }
+            }
+        };
+
+        Some(ResolvedUpstream {
+            version: new_version,
+            detail,
+            homepage: release.homepage,
+        })
+    }
+
+    fn get_current_hash(&self, upstream: &UpstreamEntry) -> String {
+        match &upstream.value {
+            UpstreamValue::Simple(hash) | UpstreamValue::Git(hash) => hash.clone(),
+            UpstreamValue::Extended { hash, .. } => hash.clone(),
+        }
+    }
+
+    /// Resolve the head commit of `branch` (or the default branch), versioned by its
+    /// commit date and short hash, e.g. `20261016.1a2b3c4`.
+    fn get_branch_head(
+        &self,
+        client: &dyn HttpGet,
+        api_base: &str,
+        branch: Option<&str>,
+        upstream: &UpstreamEntry,
+        config: &Config,
+    ) -> Result<ResolvedUpstream, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubBranchHead {
+            sha: String,
+            commit: GitHubCommitDetail,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubCommitDetail {
+            committer: GitHubCommitter,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubCommitter {
+            date: String,
+        }
+
+        let git_url = GitUrl::parse(&upstream.url);
+        let Some(GitForge::GitHub { owner, repo }) = GitForge::from_url(git_url.clone_url)
+            .filter(|_| is_git_source(upstream, &config.git_forges))
+        else {
+            eprintln!(
+                "Branch tracking needs a GitHub git source for {}",
+                self.path.display()
+            );
+            return Err(ResolveError::NoHashOrUrl);
+        };
+
+        // A ref pinned in the recipe's url is followed unless the monitoring file names one,
+        // GitHub resolves HEAD to the repository's default branch
+        let branch = branch.or(git_url.git_ref).unwrap_or("HEAD");
+        let url = format!("{api_base}/repos/{owner}/{repo}/commits/{branch}");
+
+        let head: GitHubBranchHead = get_json(client, &url)?;
+
+        let date: String = head
+            .commit
+            .committer
+            .date
+            .chars()
+            .take_while(|c| *c != 'T')
+            .filter(char::is_ascii_digit)
+            .collect();
+        let short_sha = &head.sha[..head.sha.len().min(7)];
+
+        Ok(ResolvedUpstream {
+            version: format!("{date}.{short_sha}"),
+            detail: UpstreamDetail::GitHash(head.sha.clone()),
+            homepage: None,
+        })
+    }
+
+    /// Resolve the commit `version` points at, dispatching on the upstream's git forge.
+    fn get_git_hash(
+        &self,
+        client: &dyn HttpGet,
+        version: &str,
+        upstream: &UpstreamEntry,
+    ) -> String {
+        let clean_url = GitUrl::parse(&upstream.url).clone_url;
+
+        match GitForge::from_url(clean_url) {
+            Some(GitForge::GitHub { owner, repo }) => {
+                self.get_github_hash(client, GITHUB_API, owner, repo, version)
+            }
+            // Self-hosted instances serve the API from their own host
+            Some(GitForge::GitLab { host, project }) => {
+                self.get_gitlab_hash(client, &format!("https://{host}"), project, version)
+            }
+            // cgit forges (git.kernel.org, git.savannah.gnu.org, ...) have no REST API
+            None => self.get_ls_remote_hash(clean_url, version),
+        }
+    }
+
+    fn get_ls_remote_hash(&self, url: &str, version: &str) -> String {
+        eprintln!("Resolving {} through git ls-remote on {url}", self.name());
+
+        let output = match Command::new("git")
+            .args(["ls-remote", "--tags", url])
+            .stderr(Stdio::piped())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!(
+                    "Failed to run git ls-remote for {}: {e}",
+                    self.path.display()
+                );
+                return String::new();
+            }
+        };
+
+        if !output.status.success() {
+            eprintln!(
+                "git ls-remote failed for {}: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return String::new();
+        }
+
+        find_tag_commit(&String::from_utf8_lossy(&output.stdout), version).unwrap_or_else(|| {
+            eprintln!(
+                "No tag for version {version} found on {url} for {}",
+                self.path.display()
+            );
+            String::new()
+        })
+    }
+
+    fn get_github_hash(
+        &self,
+        client: &dyn HttpGet,
+        api_base: &str,
+        owner: &str,
+        repo: &str,
+        version: &str,
+    ) -> String {
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubTag {
+            name: String,
+            commit: GitHubCommit,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubCommit {
+            sha: String,
+        }
+
+        // Get tags from GitHub API
+        let github_api_url = format!("{api_base}/repos/{owner}/{repo}/tags");
+
+        match get_json::<Vec<GitHubTag>>(client, &github_api_url) {
+            Ok(tags) => {
+                // Try to extract the version match
+                for tag in &tags {
+                    if tag.name == version || tag.name == format!("v{version}") {
+                        return tag.commit.sha.clone();
+                    }
+                }
+
+                // If no exact match, return the latest tag's commit
+                if let Some(latest_tag) = tags.first() {
+                    return latest_tag.commit.sha.clone();
+                }
+            }
+            Err(e) => eprintln!("GitHub API error for {}: {e}", self.path.display()),
+        }
+
+        // Return String::new() if fails
+        String::new()
+    }
+
+    fn get_gitlab_hash(
+        &self,
+        client: &dyn HttpGet,
+        api_base: &str,
+        project: &str,
+        version: &str,
+    ) -> String {
+        #[derive(Debug, serde::Deserialize)]
+        struct GitLabCommit {
+            id: String,
+        }
+
+        // GitLab addresses projects by their url-encoded full path
+        let project_id = project.replace('/', "%2F");
+
+        for tag in [version.to_string(), format!("v{version}")] {
+            let url = format!("{api_base}/api/v4/projects/{project_id}/repository/commits/{tag}");
+
+            match get_json::<GitLabCommit>(client, &url) {
+                Ok(commit) => return commit.id,
+                // The tag may just use the other naming scheme
+                Err(ResolveError::Server {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                }) => continue,
+                Err(e) => {
+                    eprintln!("GitLab API error for {}: {e}", self.path.display());
+                    break;
+                }
+            }
+        }
+
+        // Return String::new() if fails
+        String::new()
+    }
+}
+
+// Helper Functions
+
+/// Find the packages under `repo`: directories up to two levels deep (`<letter>/<package>`
+/// or `<package>`) holding both a `stone.yaml` and a `monitoring.yaml`.
+fn discover_packages(repo: &Path) -> impl Iterator<Item = Package> + use<> {
+    WalkDir::new(repo)
+        .min_depth(1)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if entry.file_type().is_dir() && is_utf8_path(&entry.path()) {
+                let path = entry.path().to_path_buf();
+                let manifest = path.join("stone.yaml");
+                let monitoring = path.join("monitoring.yaml");
+
+                // Only include if both manifest and monitoring files exist
+                if manifest.exists() && monitoring.exists() {
+                    Some(Package::new(path))
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        })
+}
+
+/// GET `url` through `client` and parse its body as JSON.
+fn get_json<T: DeserializeOwned>(client: &dyn HttpGet, url: &str) -> Result<T, ResolveError> {
+    serde_json::from_str(&client.get_text(url)?).map_err(|source| ResolveError::InvalidJson {
+        url: url.to_string(),
+        source,
+    })
+}
+
+/// Read and parse the YAML file at `path`.
+fn read_yaml<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let content = fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    serde_yaml::from_str(&content).map_err(|source| Error::Yaml {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Whether `path` is valid UTF-8, logging a skip when it isn't. Recipe names and the
+/// arguments handed to boulder are strings, so such a package can't be updated.
+fn is_utf8_path(path: &Path) -> bool {
+    if path.to_str().is_some() {
+        return true;
+    }
+
+    eprintln!("Skipping {} - path is not valid UTF-8", path.display());
+    false
+}
+
+/// Sort a failed request into a network or server error, passing successful responses through.
+fn check_response(result: reqwest::Result<Response>, url: &str) -> Result<Response, ResolveError> {
+    let response = result.map_err(|e| ResolveError::Network {
+        url: url.to_string(),
+        source: e,
+    })?;
+
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(ResolveError::Server {
+            url: url.to_string(),
+            status,
+        });
+    }
+
+    Ok(response)
+}
+
+/// How long a 429 `response` asks to be left alone, from its `Retry-After` seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Wait for `child`, streaming its stdout and stderr to the terminal as they are written,
+/// while keeping at most `stderr_limit` trailing bytes of its stderr for the error report.
+/// Every streamed line starts with `prefix`.
+///
+/// A child still running after `timeout` is killed and reaped, and `None` is returned.
+fn wait_streaming(
+    mut child: Child,
+    stderr_limit: usize,
+    timeout: Option<Duration>,
+    stdout_to_stderr: bool,
+    prefix: &str,
+) -> io::Result<Option<Output>> {
+    let stdout = child
+        .stdout
+        .take()
+        .expect("Failed to open recipe update process stdout");
+    let stderr = child
+        .stderr
+        .take()
+        .expect("Failed to open recipe update process stderr");
+
+    // Stream stderr on its own thread so errors show up as they happen and a chatty build
+    // can't fill the pipe and stall stdout
+    let stderr_prefix = prefix.to_string();
+    let stderr_reader = thread::spawn(move || echo_tail(stderr, stderr_limit, &stderr_prefix));
+
+    // Stream the recipe update command output to the terminal, off this thread so the
+    // timeout can be kept while it runs
+    let stdout_prefix = prefix.to_string();
+    let stdout_reader = thread::spawn(move || {
+        BufReader::new(stdout).lines().for_each(|line| match line {
+            Ok(line) if stdout_to_stderr => eprintln!("{stdout_prefix}{line}"),
+            Ok(line) => println!("{stdout_prefix}{line}"),
+            Err(_) => {}
+        });
+    });
+
+    let status = match timeout {
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    child.kill()?;
+                    child.wait()?;
+                    // Processes boulder started may still hold the pipes open, so the
+                    // readers are left to finish on their own
+                    return Ok(None);
+                }
+                thread::sleep(WAIT_POLL);
+            }
+        }
+        None => child.wait()?,
+    };
+    stdout_reader
+        .join()
+        .expect("Failed to join boulder stdout reader");
+    let stderr = stderr_reader
+        .join()
+        .expect("Failed to join boulder stderr reader")?;
+
+    Ok(Some(Output {
+        status,
+        stdout: Vec::new(),
+        stderr,
+    }))
+}
+
+/// Echo `reader` to stderr line by line until it ends, each line starting with `prefix`, and
+/// keep only the last `limit` bytes of it.
+fn echo_tail(reader: impl Read, limit: usize, prefix: &str) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(reader);
+    let mut tail = VecDeque::with_capacity(limit);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // One call per line, so lines of concurrent updates don't mix
+        eprint!("{prefix}{}", String::from_utf8_lossy(&line));
+
+        tail.extend(&line);
+        if tail.len() > limit {
+            tail.drain(..tail.len() - limit);
+        }
+    }
+
+    Ok(tail.into())
+}
+
+/// Whether `upstream_entry` is a git repository rather than an archive. Besides GitHub and
+/// GitLab repository urls and `git|` urls, a `.git` url on one of the hosts in `git_forges`
+/// is one.
+fn is_git_source(upstream_entry: &UpstreamEntry, git_forges: &[String]) -> bool {
+    // Check if explicitly marked as git
+    if upstream_entry.url.starts_with("git|") {
+        return true;
+    }
+
+    // Downloads from a forge are archives, even of a repository with `.git` in its name
+    if ["/archive/", "/releases/download/"]
+        .iter()
+        .any(|download| upstream_entry.url.contains(download))
+    {
+        return false;
+    }
+
+    // A GitHub or GitLab repository url is cloned, `.git` suffix or not
+    if is_repo_root(&upstream_entry.url) {
+        return true;
+    }
+
+    // Check for git forges
+    let host = upstream_entry
+        .url
+        .split_once("://")
+        .map(|(_, rest)| rest.split('/').next().unwrap_or(rest));
+
+    host.is_some_and(|host| git_forges.iter().any(|forge| forge == host))
+        && upstream_entry.url.contains(".git")
+}
+
+/// Whether `url` is the root of a GitHub or GitLab repository, such as
+/// `https://github.com/owner/repo`, rather than a page or file inside it.
+fn is_repo_root(url: &str) -> bool {
+    let Some((_, path)) = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+    else {
+        return false;
+    };
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+
+    // GitLab takes everything before a `/-/` for the project path, uploaded files included
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if path.contains("/uploads/")
+        || ARCHIVE_EXTENSIONS
+            .iter()
+            .any(|extension| file_name.contains(extension))
+    {
+        return false;
+    }
+
+    match GitForge::from_url(url) {
+        Some(GitForge::GitHub { owner, repo }) => path == format!("{owner}/{repo}"),
+        Some(GitForge::GitLab { project, .. }) => path == project,
+        None => false,
+    }
+}
+
+/// Find the commit `version`'s tag points at in `git ls-remote --tags` output.
+fn find_tag_commit(ls_remote: &str, version: &str) -> Option<String> {
+    let tags = [
+        format!("refs/tags/{version}"),
+        format!("refs/tags/v{version}"),
+    ];
+    let mut found = None;
+
+    for line in ls_remote.lines() {
+        let Some((sha, name)) = line.split_once('\t') else {
+            continue;
+        };
+
+        // Annotated tags are listed twice, the peeled `^{}` entry holds the commit itself
+        if let Some(name) = name.strip_suffix("^{}") {
+            if tags.iter().any(|tag| tag == name) {
+                return Some(sha.to_string());
+            }
+        } else if found.is_none() && tags.iter().any(|tag| tag == name) {
+            found = Some(sha.to_string());
+        }
+    }
+
+    found
 }
 
-#[derive(Debug, Clone)]
-enum UpstreamValue {
-    Simple(String), // Direct hash string
-    Extended {
-        hash: String,
-        properties: HashMap<String, serde_yaml::Value>,
-    },
-    Git(String),
-}
+fn get_version_source_type(
+    upstream_entry: &UpstreamEntry,
+    git_forges: &[String],
+) -> VersionSourceType {
+    if is_git_source(upstream_entry, git_forges) {
+        VersionSourceType::Git
+    } else {
+        VersionSourceType::Archive
+    }
+}
+
+/// The release of a recipe after an update: a new version starts over at 1, a new hash for
+/// the same version is a rebuild and bumps it.
+fn next_release(current: Option<u32>, new_version: bool) -> u32 {
+    if new_version {
+        1
+    } else {
+        current.unwrap_or(0) + 1
+    }
+}
+
+/// Set the `release` and `homepage` of the manifest at `path` where given, leaving the rest
+/// of the file as is.
+fn write_recipe_metadata(
+    path: &Path,
+    release: Option<u32>,
+    homepage: Option<&str>,
+) -> Result<(), Error> {
+    let mut manifest: Manifest = read_yaml(path)?;
+    manifest.release = release.or(manifest.release);
+    manifest.homepage = homepage.map(String::from).or(manifest.homepage);
+
+    manifest.write_preserving(path)
+}
+
+/// Pin each archive upstream of the manifest at `path` to its checksum, given as
+/// `(url, checksum)` pairs, leaving the rest of the file as is.
+fn write_upstream_checksums(path: &Path, checksums: &[(&str, String)]) -> Result<(), Error> {
+    let io_error = |source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let original = fs::read_to_string(path).map_err(io_error)?;
+
+    let content = checksums
+        .iter()
+        .fold(original.clone(), |content, (url, checksum)| {
+            set_upstream_hash(&content, url, checksum)
+        });
+    if content == original {
+        return Ok(());
+    }
+
+    fs::write(path, content).map_err(io_error)
+}
+
+/// Set the hash the upstream `url` is pinned to in a YAML recipe, written either as
+/// `- url: hash` or as `- url:` followed by a `hash: ...` line. A recipe without that
+/// upstream is returned as is.
+fn set_upstream_hash(content: &str, url: &str, hash: &str) -> String {
+    let entry = format!(
+        r#"(?m)^[ \t]*-[ \t]*["']?{}["']?[ \t]*:"#,
+        regex::escape(url)
+    );
+    let simple =
+        Regex::new(&format!(r"{entry}[ \t]*([^\s#]+)")).expect("Upstream hash pattern is valid");
+    let extended = Regex::new(&format!(
+        r"{entry}[ \t]*(?:#.*)?\n(?:[ \t]+[^-\s].*\n)*?[ \t]+hash[ \t]*:[ \t]*([^\s#]+)"
+    ))
+    .expect("Extended upstream hash pattern is valid");
+
+    let Some(current) = simple
+        .captures(content)
+        .or_else(|| extended.captures(content))
+        .and_then(|caps| caps.get(1))
+    else {
+        return content.to_string();
+    };
+
+    format!(
+        "{}{hash}{}",
+        &content[..current.start()],
+        &content[current.end()..]
+    )
+}
+
+/// Set the top-level scalar `key` of a YAML document to `value`, keeping its quoting and
+/// trailing comment. A missing key is added right after `version`, or at the end.
+fn set_top_level_scalar(content: &str, key: &str, value: &str) -> String {
+    let pattern = format!(
+        r#"(?m)^({}[ \t]*:[ \t]*)(?:"([^"\n]*)"|'([^'\n]*)'|([^#\n]*?))([ \t]*(?:#.*)?)$"#,
+        regex::escape(key)
+    );
+    let line = Regex::new(&pattern).expect("Scalar pattern is valid");
+
+    let Some(caps) = line.captures(content) else {
+        let version = Regex::new(r"(?m)^version[ \t]*:.*$").expect("Version pattern is valid");
+        return match version.find(content) {
+            Some(found) if key != "version" => format!(
+                "{}\n{key}: {value}{}",
+                &content[..found.end()],
+                &content[found.end()..]
+            ),
+            _ => format!("{}{key}: {value}\n", ensure_newline(content)),
+        };
+    };
+
+    let (quote, current) = match (caps.get(2), caps.get(3), caps.get(4)) {
+        (Some(current), _, _) => ("\"", current.as_str()),
+        (_, Some(current), _) => ("'", current.as_str()),
+        (_, _, current) => ("", current.map_or("", |current| current.as_str())),
+    };
+    if current == value {
+        return content.to_string();
+    }
+
+    let whole = caps.get(0).expect("Capture 0 is the whole match");
+    format!(
+        "{}{}{quote}{value}{quote}{}{}",
+        &content[..whole.start()],
+        &caps[1],
+        &caps[5],
+        &content[whole.end()..]
+    )
+}
+
+fn ensure_newline(content: &str) -> Cow<'_, str> {
+    if content.is_empty() || content.ends_with('\n') {
+        Cow::Borrowed(content)
+    } else {
+        Cow::Owned(format!("{content}\n"))
+    }
+}
+
+/// How `latest` orders against `current`, or `None` when either isn't a version we can parse.
+fn compare_versions(current: &str, latest: &str) -> Option<Ordering> {
+    if current == latest {
+        return Some(Ordering::Equal);
+    }
+    if let (Some(latest), Some(current)) = (parse_version(latest), parse_version(current)) {
+        return Some(latest.cmp(&current));
+    }
+
+    // Versions with more numbers than semver has, like `1.2.3.4`, compare number by number
+    let (mut latest, mut current) = (version_numbers(latest)?, version_numbers(current)?);
+    let len = latest.len().max(current.len());
+    latest.resize(len, 0);
+    current.resize(len, 0);
+    Some(latest.cmp(&current))
+}
+
+/// The numbers of a plain dotted `version` like `1.2.3.4`, `None` when it has anything else.
+fn version_numbers(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|number| number.parse().ok())
+        .collect()
+}
+
+/// Parse `version` as semver, padding short versions like `6.1` to `6.1.0`.
+fn parse_version(version: &str) -> Option<semver::Version> {
+    let version = version.trim_start_matches('v');
+    if let Ok(parsed) = semver::Version::parse(version) {
+        return Some(parsed);
+    }
+
+    let (core, suffix) = version.split_at(version.find(['-', '+']).unwrap_or(version.len()));
+    let padded = match core.split('.').count() {
+        1 => format!("{core}.0.0{suffix}"),
+        2 => format!("{core}.0{suffix}"),
+        _ => return None,
+    };
+
+    semver::Version::parse(&padded).ok()
+}
+
+/// Point `url` at `new_version` by replacing every whole occurrence of `old_version`, or
+/// `None` when `old_version` doesn't appear in it.
+///
+/// Release downloads often repeat the version in the tag directory
+/// (`.../v1.2.3/foo-1.2.3.tar.gz`), so all occurrences are replaced. The url may spell the
+/// version with `_` or `-` instead of `.`; when several spellings appear, the one naming the
+/// archive file wins.
+fn replace_version_in_url(url: &str, old_version: &str, new_version: &str) -> Option<String> {
+    let spellings = VERSION_SEPARATORS.map(|separator| {
+        let spell = |version: &str| version.replace('.', &separator.to_string());
+        (separator, spell(old_version), spell(new_version))
+    });
+
+    let found: Vec<_> = spellings
+        .iter()
+        .map(|(separator, old, new)| (old, new, version_positions(url, old, *separator)))
+        .filter(|(_, _, positions)| !positions.is_empty())
+        .collect();
+    let (old, new, positions) = found
+        .iter()
+        .find(|(old, _, positions)| {
+            positions.iter().any(|&start| {
+                let rest = &url[start + old.len()..];
+                ARCHIVE_EXTENSIONS.iter().any(|ext| rest.starts_with(ext))
+            })
+        })
+        .or(found.first())?;
+
+    let mut replaced = String::with_capacity(url.len());
+    let mut copied = 0;
+    for &start in positions {
+        replaced.push_str(&url[copied..start]);
+        replaced.push_str(new);
+        copied = start + old.len();
+    }
+    replaced.push_str(&url[copied..]);
+
+    Some(replaced)
+}
+
+/// Where `version` appears in `url` on its own, rather than inside a longer version like
+/// `1.2` inside `1.2.3`.
+fn version_positions(url: &str, version: &str, separator: char) -> Vec<usize> {
+    // The characters next to a version, walking away from it, extend it when they are a
+    // digit or a separator followed by a digit
+    let extends = |chars: &mut dyn Iterator<Item = char>| match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some(c) if c == '.' || c == separator => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    };
+
+    url.match_indices(version)
+        .map(|(start, _)| start)
+        .filter(|&start| {
+            !extends(&mut url[..start].chars().rev())
+                && !extends(&mut url[start + version.len()..].chars())
+        })
+        .collect()
+}
+
+// Helper Structs and enums
+/// The separators a version's components may be joined with in an upstream url.
+const VERSION_SEPARATORS: [char; 3] = ['.', '_', '-'];
+
+/// File extensions that follow the version in an archive's file name.
+const ARCHIVE_EXTENSIONS: [&str; 7] = [".tar", ".tgz", ".tbz", ".txz", ".zip", ".crate", ".gem"];
+
+/// Hosts running GitLab that don't have `gitlab` in their name.
+const GITLAB_HOSTS: [&str; 3] = ["gitlab.com", "code.videolan.org", "invent.kde.org"];
+
+/// A recipe's git upstream url taken apart: the url to clone, without the `git|` marker, and
+/// the branch or tag some recipes pin with a `#` suffix, as in
+/// `git|https://github.com/foo/bar.git#main`.
+#[derive(Debug, PartialEq, Eq)]
+struct GitUrl<'a> {
+    clone_url: &'a str,
+    git_ref: Option<&'a str>,
+}
+
+impl<'a> GitUrl<'a> {
+    fn parse(url: &'a str) -> Self {
+        let url = url.strip_prefix("git|").unwrap_or(url);
+
+        match url.split_once('#') {
+            Some((clone_url, git_ref)) => Self {
+                clone_url,
+                git_ref: Some(git_ref).filter(|git_ref| !git_ref.is_empty()),
+            },
+            None => Self {
+                clone_url: url,
+                git_ref: None,
+            },
+        }
+    }
+}
+
+/// The git forge a repository is hosted on, which decides how tags are resolved to commits.
+#[derive(Debug, PartialEq, Eq)]
+enum GitForge<'a> {
+    GitHub {
+        owner: &'a str,
+        repo: &'a str,
+    },
+    /// gitlab.com or a self-hosted instance, with the project's full (possibly nested) path.
+    GitLab {
+        host: &'a str,
+        project: &'a str,
+    },
+}
+
+impl<'a> GitForge<'a> {
+    /// Detect the forge of a repository url such as `https://invent.kde.org/plasma/kwin.git`.
+    fn from_url(url: &'a str) -> Option<Self> {
+        let (_, rest) = url.split_once("://")?;
+        let (host, path) = rest.split_once('/')?;
+
+        // GitLab puts everything that isn't the project path after a `/-/` segment
+        let path = path.split("/-/").next()?;
+        let path = path.trim_end_matches('/').trim_end_matches(".git");
+
+        if host == "github.com" {
+            let mut parts = path.split('/').filter(|part| !part.is_empty());
+            let owner = parts.next()?;
+            let repo = parts.next()?.trim_end_matches(".git");
+
+            Some(Self::GitHub { owner, repo })
+        } else if GITLAB_HOSTS.contains(&host) || host.starts_with("gitlab.") {
+            path.contains('/').then_some(Self::GitLab {
+                host,
+                project: path,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The newest upstream state resolved for a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResolvedUpstream {
+    version: String,
+    detail: UpstreamDetail,
+    /// The project homepage, when the version source reports one.
+    homepage: Option<String>,
+}
+
+/// Where the resolved version lives, depending on the upstream's source type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum UpstreamDetail {
+    /// The commit the version's tag points at.
+    GitHash(String),
+    /// The archive url for the version, with its hash when it is already known.
+    ArchiveUrl { url: String, hash: Option<String> },
+}
+
+/// The value handed to boulder's `--upstream` argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum UpstreamArg {
+    /// A git upstream pinned to a commit, rendered as `url, hash`.
+    Git { url: String, hash: String },
+    /// An archive upstream, rendered as the bare archive url.
+    Archive { url: String },
+}
+
+impl UpstreamDetail {
+    /// The hash the upstream resolved to, if it is known yet.
+    fn hash(&self) -> Option<&str> {
+        match self {
+            Self::GitHash(hash) => Some(hash),
+            Self::ArchiveUrl { hash, .. } => hash.as_deref(),
+        }
+    }
+}
+
+impl UpstreamArg {
+    /// How the upstream this argument pins is tracked.
+    fn source_type(&self) -> VersionSourceType {
+        match self {
+            Self::Git { .. } => VersionSourceType::Git,
+            Self::Archive { .. } => VersionSourceType::Archive,
+        }
+    }
+
+    /// Build the argument for `entry` from its resolved upstream detail.
+    fn new(entry: &UpstreamEntry, detail: &UpstreamDetail) -> Self {
+        match detail {
+            // boulder clones the url as given, so it gets neither the `git|` marker nor a ref
+            UpstreamDetail::GitHash(hash) => Self::Git {
+                url: GitUrl::parse(&entry.url).clone_url.to_string(),
+                hash: hash.clone(),
+            },
+            UpstreamDetail::ArchiveUrl { url, .. } => Self::Archive { url: url.clone() },
+        }
+    }
+}
+
+impl fmt::Display for VersionSourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Git => "git",
+            Self::Archive => "archive",
+        })
+    }
+}
+
+impl fmt::Display for UpstreamArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Git { url, hash } => write!(f, "{url}, {hash}"),
+            Self::Archive { url } => write!(f, "{url}"),
+        }
+    }
+}
+
+/// How a recipe's upstream is pinned: a git commit or an archive hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionSourceType {
+    Git,
+    Archive,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Releases {
+    id: Option<u32>,
+    /// Follow the head of a branch instead of tagged releases (rolling recipes).
+    #[serde(default)]
+    track_branch: bool,
+    /// The branch to follow when tracking, defaulting to the repository's default branch.
+    #[serde(default)]
+    branch: Option<String>,
+    /// An RSS or Atom release feed, used when there is no release-monitoring id.
+    #[serde(default)]
+    rss: Option<String>,
+    /// Where to look up new versions, defaulting to release-monitoring.org.
+    #[serde(default)]
+    backend: Backend,
+    /// The project name on the backend, defaulting to the recipe name (without its `python-`
+    /// prefix on PyPI).
+    #[serde(default)]
+    project: Option<String>,
+    /// The digest to pin updated archives with, overriding the config's `archive_hash`.
+    #[serde(default)]
+    archive_hash: Option<HashAlgorithm>,
+    /// Let release-monitoring's newest version win even when it is a pre-release.
+    #[serde(default)]
+    prereleases: bool,
+    /// Leave out versions marked as pre-releases, overriding the config's `stable_only`.
+    #[serde(default)]
+    stable_only: Option<bool>,
+    /// Never pick versions matching this regular expression.
+    #[serde(default)]
+    ignore_version_regex: Option<String>,
+}
+
+impl Releases {
+    /// The backend this package's new versions are looked up with.
+    /// `name` is the recipe name, which projects are looked up by when not given explicitly.
+    fn backend(&self, name: &str, filter: VersionFilter) -> Box<dyn VersionBackend> {
+        match self.backend {
+            Backend::ReleaseMonitoring if self.id.is_none() && self.rss.is_some() => {
+                Box::new(RssFeed {
+                    url: self.rss.clone().unwrap_or_default(),
+                })
+            }
+            Backend::ReleaseMonitoring => Box::new(ReleaseMonitoring {
+                api_base: RELEASE_MONITORING_API.to_string(),
+                id: self.id,
+                name: name.to_string(),
+                prereleases: self.prereleases,
+                filter,
+            }),
+            Backend::GitHub => Box::new(GitHubReleases {
+                api_base: GITHUB_API.to_string(),
+            }),
+            Backend::PyPI => Box::new(PyPI {
+                api_base: PYPI_API.to_string(),
+                project: self
+                    .project
+                    .clone()
+                    .unwrap_or_else(|| name.strip_prefix("python-").unwrap_or(name).to_string()),
+                filter,
+            }),
+            Backend::CratesIo => Box::new(CratesIo {
+                api_base: CRATES_IO_API.to_string(),
+                name: self.project.clone().unwrap_or_else(|| name.to_string()),
+                filter,
+            }),
+        }
+    }
+
+    /// Which versions the package may move to, as set here or in `config`.
+    fn version_filter(&self, config: &Config) -> Result<VersionFilter, ResolveError> {
+        VersionFilter::new(
+            self.stable_only,
+            self.ignore_version_regex.as_deref(),
+            config,
+        )
+    }
+}
+
+/// Where a package's upstream versions are looked up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+enum Backend {
+    /// The repository's latest GitHub release.
+    #[serde(rename = "github")]
+    GitHub,
+    /// The project's latest release on PyPI.
+    #[serde(rename = "pypi")]
+    PyPI,
+    /// The crate's latest release on crates.io.
+    #[serde(rename = "crates.io", alias = "crates")]
+    CratesIo,
+    /// release-monitoring.org's project API, also used for backends without direct support
+    /// (npm, ...).
+    #[default]
+    #[serde(rename = "release-monitoring", other)]
+    ReleaseMonitoring,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Monitoring {
+    releases: Releases,
+    #[serde(default)]
+    #[allow(dead_code)]
+    security: Option<serde_yaml::Value>,
+}
+
+#[derive(Debug, Clone)]
+enum UpstreamValue {
+    Simple(String), // Direct hash string
+    Extended {
+        hash: String,
+        #[allow(dead_code)]
+        properties: HashMap<String, serde_yaml::Value>,
+    },
+    Git(String),
+}
+
+/// One of a recipe's upstream sources: where it's fetched from and what pins it.
+#[derive(Debug, Clone)]
+pub struct UpstreamEntry {
+    url: String,
+    value: UpstreamValue,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Manifest {
+    name: String,
+    version: String,
+    #[serde(default)]
+    release: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_upstreams")]
+    upstreams: Option<Vec<UpstreamEntry>>,
+    #[serde(default)]
+    homepage: Option<String>,
+}
+
+impl Manifest {
+    /// Write this manifest's `version`, `release` and `homepage` into the recipe at `path`.
+    ///
+    /// Only those values change: comments, key order and formatting stay as they were, and
+    /// a manifest that already matches the file isn't rewritten at all.
+    fn write_preserving(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let original = fs::read_to_string(path).map_err(io_error)?;
+
+        let mut content = set_top_level_scalar(&original, "version", &self.version);
+        if let Some(release) = self.release {
+            content = set_top_level_scalar(&content, "release", &release.to_string());
+        }
+        if let Some(homepage) = &self.homepage {
+            content = set_top_level_scalar(&content, "homepage", homepage);
+        }
+
+        if content == original {
+            return Ok(());
+        }
+
+        fs::write(path, content).map_err(io_error)
+    }
+}
+
+// Custom deserailizer for upstreams
+fn deserialize_upstreams<'de, D>(deserializer: D) -> Result<Option<Vec<UpstreamEntry>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw: Option<Vec<HashMap<String, serde_yaml::Value>>> = Option::deserialize(deserializer)?;
+
+    Ok(raw.map(|upstreams| {
+        upstreams
+            .into_iter()
+            .filter_map(|map| {
+                let (url, mut value) = map.into_iter().next()?;
+
+                // Aliases are resolved by serde_yaml, but `<<: *anchor` merge keys are not
+                value.apply_merge().ok()?;
+
+                let upstream_value = match value {
+                    // Simple string hash
+                    serde_yaml::Value::String(hash) => {
+                        if url.starts_with("git|") {
+                            UpstreamValue::Git(hash)
+                        } else {
+                            UpstreamValue::Simple(hash)
+                        }
+                    }
+                    // Placeholder upstream without a hash yet
+                    serde_yaml::Value::Null => {
+                        if url.starts_with("git|") {
+                            UpstreamValue::Git(String::new())
+                        } else {
+                            UpstreamValue::Simple(String::new())
+                        }
+                    }
+                    // Extended object with hash + properties
+                    serde_yaml::Value::Mapping(map) => {
+                        let hash = match map.get("hash") {
+                            Some(hash) => hash.as_str()?.to_string(),
+                            None => String::new(),
+                        };
+                        let mut properties = HashMap::new();
+
+                        for (k, v) in map {
+                            if let serde_yaml::Value::String(key) = k
+                                && key != "hash"
+                            {
+                                properties.insert(key.clone(), v.clone());
+                            }
+                        }
+
+                        if url.starts_with("git|") {
+                            UpstreamValue::Git(hash)
+                        } else {
+                            UpstreamValue::Extended { hash, properties }
+                        }
+                    }
+                    _ => return None,
+                };
+
+                Some(UpstreamEntry {
+                    url,
+                    value: upstream_value,
+                })
+            })
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers GETs of the urls in `responses` with their canned body, and 404s the rest.
+    pub(super) struct MockHttp {
+        responses: HashMap<String, String>,
+        release_cache: ReleaseCache,
+    }
+
+    impl MockHttp {
+        pub(super) fn new(responses: &[(&str, &str)]) -> Self {
+            Self {
+                responses: responses
+                    .iter()
+                    .map(|(url, body)| (url.to_string(), body.to_string()))
+                    .collect(),
+                release_cache: ReleaseCache::load(None, Duration::from_secs(60), false),
+            }
+        }
+    }
+
+    impl HttpGet for MockHttp {
+        fn get_text(&self, url: &str) -> Result<String, ResolveError> {
+            self.responses
+                .get(url)
+                .cloned()
+                .ok_or_else(|| ResolveError::Server {
+                    url: url.to_string(),
+                    status: StatusCode::NOT_FOUND,
+                })
+        }
+
+        fn download(&self, url: &str, sink: &mut dyn Write) -> Result<Download, ResolveError> {
+            let body = self.get_text(url)?;
+            sink.write_all(body.as_bytes())
+                .expect("Writing a download can't fail in tests");
+
+            Ok(Download {
+                len: body.len() as u64,
+                content_type: None,
+            })
+        }
+
+        fn release_cache(&self) -> &ReleaseCache {
+            &self.release_cache
+        }
+    }
+
+    /// Serve canned HTTP `(status, body)` responses in order on a local port. Returns the
+    /// server's base url and a handle yielding the request lines it received.
+    pub(super) fn serve(responses: &[(&str, &str)]) -> (String, thread::JoinHandle<Vec<String>>) {
+        use std::io::Write;
+
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind test server");
+        let base = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .expect("Failed to get test server address")
+        );
+        let responses: Vec<String> = responses
+            .iter()
+            .map(|(status, body)| {
+                format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            })
+            .collect();
+
+        let server = thread::spawn(move || {
+            responses
+                .iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().expect("Failed to accept request");
+                    let mut request = [0u8; 4096];
+                    let read = stream.read(&mut request).expect("Failed to read request");
+                    stream
+                        .write_all(response.as_bytes())
+                        .expect("Failed to write response");
+
+                    String::from_utf8_lossy(&request[..read])
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect()
+        });
+
+        (base, server)
+    }
+
+    #[test]
+    fn test_branch_head_resolution() {
+        let (base, server) = serve(&[(
+            "200 OK",
+            r#"{"sha":"1a2b3c4d5e6f7a8b9c0d","commit":{"committer":{"date":"2026-10-16T08:30:00Z"}}}"#,
+        )]);
+        let package = Package::new("cosmic-applets");
+        let upstream = UpstreamEntry {
+            url: "git|https://github.com/pop-os/cosmic-applets.git".to_string(),
+            value: UpstreamValue::Git("0123abcd".to_string()),
+        };
+
+        let resolved = package
+            .get_branch_head(
+                &HttpClient::new(&Config::default()),
+                &base,
+                Some("master"),
+                &upstream,
+                &Config::default(),
+            )
+            .expect("Branch head should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /repos/pop-os/cosmic-applets/commits/master "));
+        assert_eq!(
+            resolved,
+            ResolvedUpstream {
+                version: "20261016.1a2b3c4".to_string(),
+                detail: UpstreamDetail::GitHash("1a2b3c4d5e6f7a8b9c0d".to_string()),
+                homepage: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_backend_archive_url_is_used_as_is() {
+        let package = Package::new("python-requests");
+        let upstream = UpstreamEntry {
+            url: "https://files.example/ab/cd/requests-2.31.0.tar.gz".to_string(),
+            value: UpstreamValue::Simple("0123abcd".to_string()),
+        };
+        let release = LatestRelease {
+            archive_url: Some("https://files.example/requests-2.32.3.tar.gz".to_string()),
+            ..release("2.32.3")
+        };
+
+        // PyPI file urls can't be derived from the old one, the backend's sdist wins
+        let resolved = package
+            .resolve_upstream(
+                &HttpClient::new(&Config::default()),
+                release,
+                &upstream,
+                "2.31.0",
+                &Config::default(),
+            )
+            .expect("Archive upstream should resolve");
+        assert_eq!(
+            resolved.detail,
+            UpstreamDetail::ArchiveUrl {
+                url: "https://files.example/requests-2.32.3.tar.gz".to_string(),
+                hash: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_monitoring_backend_parses() {
+        let backend = |yaml: &str| {
+            serde_yaml::from_str::<Monitoring>(yaml)
+                .expect("Failed to parse monitoring")
+                .releases
+                .backend
+        };
+
+        assert_eq!(
+            backend("releases:\n    id: 1\n"),
+            Backend::ReleaseMonitoring
+        );
+        assert_eq!(backend("releases:\n    backend: github\n"), Backend::GitHub);
+        assert_eq!(backend("releases:\n    backend: pypi\n"), Backend::PyPI);
+        assert_eq!(
+            backend("releases:\n    backend: crates.io\n"),
+            Backend::CratesIo
+        );
+        // Backends without direct support go through release-monitoring
+        assert_eq!(
+            backend("releases:\n    id: 1\n    backend: npm\n"),
+            Backend::ReleaseMonitoring
+        );
+    }
+
+    #[test]
+    fn test_upstream_arg_git_rendering() {
+        let entry = UpstreamEntry {
+            url: "git|https://github.com/pop-os/cosmic-applets.git".to_string(),
+            value: UpstreamValue::Git("0123abcd".to_string()),
+        };
+
+        let arg = UpstreamArg::new(&entry, &UpstreamDetail::GitHash("4567cdef".to_string()));
+
+        assert_eq!(
+            arg,
+            UpstreamArg::Git {
+                url: "https://github.com/pop-os/cosmic-applets.git".to_string(),
+                hash: "4567cdef".to_string(),
+            }
+        );
+        assert_eq!(
+            arg.to_string(),
+            "https://github.com/pop-os/cosmic-applets.git, 4567cdef"
+        );
+    }
+
+    #[test]
+    fn test_upstream_arg_archive_rendering() {
+        let entry = UpstreamEntry {
+            url: "https://example.org/releases/foo-1.0.0.tar.xz".to_string(),
+            value: UpstreamValue::Simple("0123abcd".to_string()),
+        };
+
+        let arg = UpstreamArg::new(
+            &entry,
+            &UpstreamDetail::ArchiveUrl {
+                url: "https://example.org/releases/foo-1.1.0.tar.xz".to_string(),
+                hash: None,
+            },
+        );
+
+        assert_eq!(
+            arg,
+            UpstreamArg::Archive {
+                url: "https://example.org/releases/foo-1.1.0.tar.xz".to_string(),
+            }
+        );
+        assert_eq!(
+            arg.to_string(),
+            "https://example.org/releases/foo-1.1.0.tar.xz"
+        );
+    }
+
+    #[test]
+    fn test_resolve_upstream_git_hash() {
+        let package = Package::new("foo");
+        let upstream = UpstreamEntry {
+            url: "git|https://github.com/pop-os/cosmic-applets.git".to_string(),
+            value: UpstreamValue::Git("0123abcd".to_string()),
+        };
+
+        let resolved = package
+            .resolve_upstream(
+                &HttpClient::new(&Config::default()),
+                release("1.0.0"),
+                &upstream,
+                "1.0.0",
+                &Config::default(),
+            )
+            .expect("Git upstream should resolve");
+
+        assert_eq!(
+            resolved,
+            ResolvedUpstream {
+                version: "1.0.0".to_string(),
+                detail: UpstreamDetail::GitHash("0123abcd".to_string()),
+                homepage: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_upstream_archive_url() {
+        let package = Package::new("foo");
+        let upstream = UpstreamEntry {
+            url: "https://example.org/releases/foo-1.0.0.tar.xz".to_string(),
+            value: UpstreamValue::Simple("0123abcd".to_string()),
+        };
+
+        let unchanged = package
+            .resolve_upstream(
+                &HttpClient::new(&Config::default()),
+                release("1.0.0"),
+                &upstream,
+                "1.0.0",
+                &Config::default(),
+            )
+            .expect("Archive upstream should resolve");
+        assert_eq!(
+            unchanged.detail,
+            UpstreamDetail::ArchiveUrl {
+                url: "https://example.org/releases/foo-1.0.0.tar.xz".to_string(),
+                hash: Some("0123abcd".to_string()),
+            }
+        );
+
+        let bumped = package
+            .resolve_upstream(
+                &HttpClient::new(&Config::default()),
+                release("1.1.0"),
+                &upstream,
+                "1.0.0",
+                &Config::default(),
+            )
+            .expect("Archive upstream should resolve");
+        assert_eq!(
+            bumped,
+            ResolvedUpstream {
+                version: "1.1.0".to_string(),
+                detail: UpstreamDetail::ArchiveUrl {
+                    url: "https://example.org/releases/foo-1.1.0.tar.xz".to_string(),
+                    hash: None,
+                },
+                homepage: None,
+            }
+        );
+
+        // The same version spelled differently is no reason to rewrite the upstream
+        let respelled = package
+            .resolve_upstream(
+                &HttpClient::new(&Config::default()),
+                release("v1.0"),
+                &upstream,
+                "1.0.0",
+                &Config::default(),
+            )
+            .expect("Archive upstream should resolve");
+        assert_eq!(respelled.detail, unchanged.detail);
+    }
+
+    #[test]
+    fn test_wait_streaming_keeps_exit_status() {
+        let child = Command::new("sh")
+            .args([
+                "-c",
+                "echo building; echo 'error: no such file' >&2; exit 3",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn stub boulder");
+
+        let output = wait_streaming(child, 4096, None, false, "")
+            .expect("Failed to wait for stub boulder")
+            .expect("Stub boulder has no timeout");
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stderr, b"error: no such file\n");
+    }
+
+    #[test]
+    fn test_wait_streaming_kills_on_timeout() {
+        // Stand-in for a boulder build that hangs
+        let child = Command::new("sh")
+            .args(["-c", "echo building; exec sleep 60"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn stub boulder");
+        let pid = child.id();
+        let started = Instant::now();
+
+        let output = wait_streaming(child, 4096, Some(Duration::from_millis(300)), false, "")
+            .expect("Failed to wait for stub boulder");
+
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(30));
+        // Killed and reaped, so not even a zombie is left behind
+        assert!(!Path::new(&format!("/proc/{pid}")).exists());
+    }
+
+    #[test]
+    fn test_boulder_stderr_retention_is_bounded() {
+        // Stand-in for boulder that writes ~1 MiB to stderr and ends with a marker line
+        let child = Command::new("sh")
+            .args([
+                "-c",
+                "head -c 1048576 /dev/zero | tr '\\0' x >&2; echo done >&2; echo stdout line",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn stub boulder");
+
+        let output = wait_streaming(child, 4096, None, false, "")
+            .expect("Failed to wait for stub boulder")
+            .expect("Stub boulder has no timeout");
+
+        assert!(output.status.success());
+        assert_eq!(output.stderr.len(), 4096);
+        assert!(output.stderr.ends_with(b"xxxxdone\n"));
+    }
+
+    #[test]
+    fn test_git_source_detection() {
+        let git_forges = Config::default().git_forges;
+        let is_git = |url: &str| {
+            is_git_source(
+                &UpstreamEntry {
+                    url: url.to_string(),
+                    value: UpstreamValue::Simple("0123abcd".to_string()),
+                },
+                &git_forges,
+            )
+        };
+
+        // Repository roots are cloned
+        assert!(is_git("https://github.com/owner/repo"));
+        assert!(is_git("https://github.com/owner/repo/"));
+        assert!(is_git("https://github.com/owner/repo.git"));
+        assert!(is_git("https://gitlab.com/group/sub/project"));
+        assert!(is_git("https://git.kernel.org/pub/scm/utils/dtc/dtc.git"));
+        // Downloads and files are archives
+        assert!(!is_git("https://github.com/owner/repo/archive/v1.0.tar.gz"));
+        assert!(!is_git(
+            "https://github.com/owner/repo/archive/refs/tags/v1.0.tar.gz"
+        ));
+        assert!(!is_git(
+            "https://github.com/owner/repo/releases/download/v1.0/repo-1.0.tar.xz"
+        ));
+        assert!(!is_git(
+            "https://github.com/owner/repo.github.io/archive/v1.0.tar.gz"
+        ));
+        assert!(!is_git(
+            "https://gitlab.com/group/project/-/archive/v1.0/project-v1.0.tar.gz"
+        ));
+        assert!(!is_git("https://example.org/releases/foo-1.0.tar.xz"));
+        assert!(is_git("https://codeberg.org/owner/repo.git"));
+        // GitLab
+        assert!(is_git("https://gitlab.com/group/proj"));
+        assert!(is_git("https://gitlab.com/group/proj.git"));
+        assert!(is_git("https://invent.kde.org/plasma/kwin/"));
+        assert!(!is_git(
+            "https://gitlab.com/group/proj/uploads/0123456789abcdef/proj-1.0.tar.xz"
+        ));
+        assert!(!is_git(
+            "https://gitlab.freedesktop.org/mesa/drm/-/archive/libdrm-2.4.120/drm-libdrm-2.4.120.tar.bz2"
+        ));
+        assert!(!is_git(
+            "https://gitlab.gnome.org/GNOME/proj/-/releases/1.0/downloads/proj-1.0.tar.xz"
+        ));
+        assert!(!is_git("https://gitlab.com/group/proj/proj-1.0.zip"));
+    }
+
+    #[test]
+    fn test_configured_git_forge_is_recognized() {
+        let upstream = UpstreamEntry {
+            url: "https://git.example.org/tools/foo.git".to_string(),
+            value: UpstreamValue::Simple("0123abcd".to_string()),
+        };
+        let config: Config =
+            toml::from_str(r#"git_forges = ["git.example.org"]"#).expect("Failed to parse config");
+
+        assert_eq!(
+            get_version_source_type(&upstream, &Config::default().git_forges),
+            VersionSourceType::Archive
+        );
+        assert_eq!(
+            get_version_source_type(&upstream, &config.git_forges),
+            VersionSourceType::Git
+        );
+    }
+
+    #[test]
+    fn test_git_url_parsing() {
+        assert_eq!(
+            GitUrl::parse("git|https://github.com/pop-os/cosmic-applets.git"),
+            GitUrl {
+                clone_url: "https://github.com/pop-os/cosmic-applets.git",
+                git_ref: None,
+            }
+        );
+        assert_eq!(
+            GitUrl::parse("git|https://github.com/pop-os/cosmic-applets.git#main"),
+            GitUrl {
+                clone_url: "https://github.com/pop-os/cosmic-applets.git",
+                git_ref: Some("main"),
+            }
+        );
+        assert_eq!(
+            GitUrl::parse("https://gitlab.com/inkscape/inkscape.git"),
+            GitUrl {
+                clone_url: "https://gitlab.com/inkscape/inkscape.git",
+                git_ref: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_git_forge_detection() {
+        assert_eq!(
+            GitForge::from_url("https://github.com/pop-os/cosmic-applets.git"),
+            Some(GitForge::GitHub {
+                owner: "pop-os",
+                repo: "cosmic-applets",
+            })
+        );
+        assert_eq!(
+            GitForge::from_url("https://gitlab.com/inkscape/inkscape.git"),
+            Some(GitForge::GitLab {
+                host: "gitlab.com",
+                project: "inkscape/inkscape",
+            })
+        );
+        assert_eq!(
+            GitForge::from_url("https://code.videolan.org/videolan/dav1d.git"),
+            Some(GitForge::GitLab {
+                host: "code.videolan.org",
+                project: "videolan/dav1d",
+            })
+        );
+        assert_eq!(
+            GitForge::from_url("https://invent.kde.org/plasma/kwin"),
+            Some(GitForge::GitLab {
+                host: "invent.kde.org",
+                project: "plasma/kwin",
+            })
+        );
+        assert_eq!(
+            GitForge::from_url("https://gitlab.freedesktop.org/mesa/drm/-/tree/main"),
+            Some(GitForge::GitLab {
+                host: "gitlab.freedesktop.org",
+                project: "mesa/drm",
+            })
+        );
+        assert_eq!(
+            GitForge::from_url("https://git.kernel.org/pub/scm/linux/kernel/git/stable/linux.git"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_gitlab_hash_resolution() {
+        let (base, server) = serve(&[
+            ("404 Not Found", r#"{"message":"404 Commit Not Found"}"#),
+            (
+                "200 OK",
+                r#"{"id":"9f8e7d6c5b4a39281706f5e4d3c2b1a098765432","short_id":"9f8e7d6c"}"#,
+            ),
+        ]);
+        let package = Package::new("dav1d");
+
+        let hash = package.get_gitlab_hash(
+            &HttpClient::new(&Config::default()),
+            &base,
+            "videolan/dav1d",
+            "1.5.1",
+        );
+        let requests = server.join().expect("Test server panicked");
+
+        assert_eq!(hash, "9f8e7d6c5b4a39281706f5e4d3c2b1a098765432");
+        assert!(
+            requests[0]
+                .starts_with("GET /api/v4/projects/videolan%2Fdav1d/repository/commits/1.5.1 ")
+        );
+        assert!(
+            requests[1]
+                .starts_with("GET /api/v4/projects/videolan%2Fdav1d/repository/commits/v1.5.1 ")
+        );
+    }
+
+    #[test]
+    fn test_find_tag_commit_in_ls_remote_output() {
+        let ls_remote = "\
+1111111111111111111111111111111111111111\trefs/tags/v6.11
+2222222222222222222222222222222222222222\trefs/tags/v6.11^{}
+3333333333333333333333333333333333333333\trefs/tags/v6.12
+4444444444444444444444444444444444444444\trefs/tags/v6.12^{}
+5555555555555555555555555555555555555555\trefs/tags/1.2.3
+";
+
+        // Annotated tags resolve to the peeled commit
+        assert_eq!(
+            find_tag_commit(ls_remote, "6.12").as_deref(),
+            Some("4444444444444444444444444444444444444444")
+        );
+        // Lightweight tags resolve to the tag's own sha
+        assert_eq!(
+            find_tag_commit(ls_remote, "1.2.3").as_deref(),
+            Some("5555555555555555555555555555555555555555")
+        );
+        // Partial versions don't match longer tags
+        assert_eq!(find_tag_commit(ls_remote, "6.1"), None);
+    }
+
+    #[test]
+    fn test_connection_refused_is_a_network_error() {
+        // Grab a free port and close it again so nothing is listening there
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to reserve a local port")
+            .port();
+        let url = format!("http://127.0.0.1:{port}/api/project/1");
+
+        let result = check_response(reqwest::blocking::get(&url), &url);
+
+        assert!(
+            matches!(result, Err(ResolveError::Network { .. })),
+            "Connection refusal should map to a network error, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_http_500_is_a_server_error() {
+        let (base, server) = serve(&[("500 Internal Server Error", "")]);
+        let url = format!("{base}/api/project/1");
+
+        let result = check_response(reqwest::blocking::get(&url), &url);
+        server.join().expect("Test server panicked");
+
+        assert!(
+            matches!(
+                result,
+                Err(ResolveError::Server {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    ..
+                })
+            ),
+            "A 500 should map to a server error, got {result:?}"
+        );
+    }
+
+    /// A client that retries `max_retries` times without waiting around between attempts.
+    fn quick_retry_client(max_retries: u32) -> HttpClient {
+        HttpClient {
+            backoff: Duration::from_millis(1),
+            ..HttpClient::new(&Config {
+                max_retries,
+                ..Config::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_server_errors_are_retried() {
+        let (base, server) = serve(&[
+            ("503 Service Unavailable", ""),
+            ("502 Bad Gateway", ""),
+            ("200 OK", r#"{"version": "1.0.0"}"#),
+        ]);
+        let url = format!("{base}/api/project/1");
+
+        let result = quick_retry_client(2).get(&url);
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(
+            result.is_ok(),
+            "The third attempt should succeed, got {result:?}"
+        );
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[test]
+    fn test_retries_give_up_after_max_retries() {
+        let (base, server) = serve(&[
+            ("500 Internal Server Error", ""),
+            ("500 Internal Server Error", ""),
+        ]);
+        let url = format!("{base}/api/project/1");
+
+        let result = quick_retry_client(1).get(&url);
+        server.join().expect("Test server panicked");
+
+        assert!(
+            matches!(
+                &result,
+                Err(ResolveError::RetriesExhausted { last, .. })
+                    if matches!(**last, ResolveError::Server { .. })
+            ),
+            "The last server error should be returned, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_honors_retry_after() {
+        let (base, server) = serve(&[
+            ("429 Too Many Requests\r\nRetry-After: 1", ""),
+            ("200 OK", r#"{"version": "1.0.0"}"#),
+        ]);
+        let url = format!("{base}/api/project/1");
+
+        let start = Instant::now();
+        let result = quick_retry_client(1).get(&url);
+        server.join().expect("Test server panicked");
+
+        assert!(result.is_ok(), "The retry should succeed, got {result:?}");
+        // The backoff alone is a millisecond, only the header explains a full second
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_requests_are_spaced_by_min_interval() {
+        let (base, server) = serve(&[("200 OK", "{}"), ("200 OK", "{}")]);
+        let url = format!("{base}/api/project/1");
+        let client = HttpClient::new(&Config {
+            request_interval_ms: 200,
+            ..Config::default()
+        });
+
+        let start = Instant::now();
+        client.get(&url).expect("First request should succeed");
+        client.get(&url).expect("Second request should succeed");
+        server.join().expect("Test server panicked");
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_client_errors_are_not_retried() {
+        let (base, server) = serve(&[("404 Not Found", "")]);
+        let url = format!("{base}/api/project/1");
+
+        // A retry would hit the closed test server and turn into a network error
+        let result = quick_retry_client(3).get(&url);
+        server.join().expect("Test server panicked");
+
+        assert!(
+            matches!(
+                result,
+                Err(ResolveError::Server {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                })
+            ),
+            "A 404 should be returned straight away, got {result:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_package_dir_is_skipped() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        let valid = repo.path().join("foo");
+        let invalid = repo.path().join(OsStr::from_bytes(b"b\xffr"));
+
+        for dir in [&valid, &invalid] {
+            fs::create_dir(dir).expect("Failed to create package dir");
+            fs::write(dir.join("stone.yaml"), "name: pkg\nversion: 1.0.0\n")
+                .expect("Failed to write stone.yaml");
+            fs::write(dir.join("monitoring.yaml"), "releases:\n    id: 1\n")
+                .expect("Failed to write monitoring.yaml");
+        }
+
+        let state = RepoState::new(Repo::Local(repo.path().to_path_buf()));
+
+        assert!(state.packages.iter().any(|package| package.path == valid));
+        assert!(
+            state
+                .packages
+                .iter()
+                .all(|package| !package.path.starts_with(&invalid)),
+            "Non-UTF-8 package dir should be skipped"
+        );
+    }
+
+    #[test]
+    fn test_aliased_upstream_parses() {
+        let manifest_content = r#"
+name: foo
+version: 1.0.0
+x-common: &common
+    hash: 0123abcd
+    strip-dirs: 1
+upstreams:
+    - https://example.org/releases/foo-1.0.0.tar.xz: *common
+    - https://example.org/releases/foo-data-1.0.0.tar.xz:
+        <<: *common
+        hash: 4567cdef
+    - https://example.org/releases/foo-docs-1.0.0.tar.xz:
+        <<: *common
+        unpack: false
+"#;
+        let manifest: Manifest =
+            serde_yaml::from_str(manifest_content).expect("Failed to parse manifest");
+        let upstreams = manifest.upstreams.expect("Manifest should have upstreams");
+        assert_eq!(upstreams.len(), 3);
+
+        let hash_and_properties = |entry: &UpstreamEntry| match &entry.value {
+            UpstreamValue::Extended { hash, properties } => (hash.clone(), properties.clone()),
+            value => panic!("Expected an extended upstream, got {value:?}"),
+        };
+
+        // Plain alias of the anchored block
+        let (hash, properties) = hash_and_properties(&upstreams[0]);
+        assert_eq!(hash, "0123abcd");
+        assert_eq!(
+            properties.get("strip-dirs"),
+            Some(&serde_yaml::Value::from(1))
+        );
+
+        // Merged block overriding the anchored hash
+        let (hash, properties) = hash_and_properties(&upstreams[1]);
+        assert_eq!(hash, "4567cdef");
+        assert_eq!(
+            properties.get("strip-dirs"),
+            Some(&serde_yaml::Value::from(1))
+        );
+        assert!(!properties.contains_key("<<"));
+
+        // Merged block inheriting the anchored hash
+        let (hash, properties) = hash_and_properties(&upstreams[2]);
+        assert_eq!(hash, "0123abcd");
+        assert_eq!(
+            properties.get("unpack"),
+            Some(&serde_yaml::Value::from(false))
+        );
+        assert_eq!(
+            properties.get("strip-dirs"),
+            Some(&serde_yaml::Value::from(1))
+        );
+    }
+
+    #[test]
+    fn test_identical_hashes_are_up_to_date() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let package = write_fake_package(dir.path(), "foo");
+        fs::write(
+            package.join("stone.yaml"),
+            r#"
+name: foo
+version: 1.0.0
+upstreams:
+    - git|https://github.com/example/foo.git: 0123456789abcdef0123456789abcdef01234567
+"#,
+        )
+        .expect("Failed to write stone.yaml");
+        let mut package = Package::new(package);
+
+        let client = cached_client("1.0.0", None);
+
+        let outcome = package
+            .update(&client, &Config::default())
+            .expect("Update should succeed");
+
+        assert_eq!(outcome, UpdateOutcome::UpToDate);
+    }
+
+    #[test]
+    fn test_replace_version_in_url() {
+        assert_eq!(
+            replace_version_in_url("https://example.org/foo-1.2.3.tar.xz", "1.2.3", "1.2.4")
+                .as_deref(),
+            Some("https://example.org/foo-1.2.4.tar.xz")
+        );
+        // A `v` in front of the version is kept, and so is a repeated tag directory
+        assert_eq!(
+            replace_version_in_url(
+                "https://github.com/o/foo/releases/download/v1.2.3/foo-1.2.3.tar.gz",
+                "1.2.3",
+                "1.3.0"
+            )
+            .as_deref(),
+            Some("https://github.com/o/foo/releases/download/v1.3.0/foo-1.3.0.tar.gz")
+        );
+        // A dash in front of the version isn't part of it, even after a digit
+        assert_eq!(
+            replace_version_in_url("https://example.org/gtk4-4.14.tar.xz", "4.14", "4.16")
+                .as_deref(),
+            Some("https://example.org/gtk4-4.16.tar.xz")
+        );
+    }
+
+    #[test]
+    fn test_replace_version_in_url_skips_longer_versions() {
+        // `1.2` used to match inside `1.2.3`, turning it into `1.3.3`
+        assert_eq!(
+            replace_version_in_url("https://example.org/foo-1.2.3.tar.xz", "1.2", "1.3"),
+            None
+        );
+        assert_eq!(
+            replace_version_in_url("https://example.org/1.2.3/foo-1.2.tar.xz", "1.2", "1.3")
+                .as_deref(),
+            Some("https://example.org/1.2.3/foo-1.3.tar.xz")
+        );
+        assert_eq!(
+            replace_version_in_url("https://example.org/foo-11.2.tar.xz", "1.2", "1.3"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_replace_version_in_url_other_separators() {
+        assert_eq!(
+            replace_version_in_url("https://example.org/foo-1_2_3.tar.gz", "1.2.3", "1.2.4")
+                .as_deref(),
+            Some("https://example.org/foo-1_2_4.tar.gz")
+        );
+        assert_eq!(
+            replace_version_in_url("https://example.org/foo-1-2-3.zip", "1.2.3", "2.0.0")
+                .as_deref(),
+            Some("https://example.org/foo-2-0-0.zip")
+        );
+        // The spelling naming the archive wins over the one in the directory
+        assert_eq!(
+            replace_version_in_url(
+                "https://example.org/1.2.3/foo_1_2_3.tar.gz",
+                "1.2.3",
+                "1.2.4"
+            )
+            .as_deref(),
+            Some("https://example.org/1.2.3/foo_1_2_4.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_next_release() {
+        assert_eq!(next_release(Some(3), true), 1);
+        assert_eq!(next_release(Some(3), false), 4);
+        assert_eq!(next_release(None, false), 1);
+    }
+
+    #[test]
+    fn test_write_release_keeps_the_rest_of_the_manifest() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("stone.yaml");
+        fs::write(
+            &path,
+            "name: foo\nversion: 1.0.0\nrelease   : 12 # bumped by hand\n",
+        )
+        .expect("Failed to write stone.yaml");
+
+        write_recipe_metadata(&path, Some(13), None).expect("Failed to write release");
+        assert_eq!(
+            fs::read_to_string(&path).expect("Failed to read stone.yaml"),
+            "name: foo\nversion: 1.0.0\nrelease   : 13 # bumped by hand\n"
+        );
+
+        fs::write(&path, "name: foo\nversion: 1.0.0\n").expect("Failed to write stone.yaml");
+        write_recipe_metadata(&path, Some(1), None).expect("Failed to write release");
+        assert_eq!(
+            fs::read_to_string(&path).expect("Failed to read stone.yaml"),
+            "name: foo\nversion: 1.0.0\nrelease: 1\n"
+        );
+    }
+
+    #[test]
+    fn test_write_preserving_no_op_is_byte_for_byte() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("stone.yaml");
+        let content = r#"# Maintained by hand, keep the comments
+name        : foo
+version     : "1.0.0" # quoted on purpose
+release     : 7
+homepage    : 'https://example.org/foo'
+upstreams   :
+    # The release tarball
+    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd
+summary     : Foo things
+"#;
+        fs::write(&path, content).expect("Failed to write stone.yaml");
+
+        let manifest: Manifest = read_yaml(&path).expect("Failed to read stone.yaml");
+        manifest
+            .write_preserving(&path)
+            .expect("Failed to rewrite stone.yaml");
+
+        assert_eq!(
+            fs::read_to_string(&path).expect("Failed to read stone.yaml"),
+            content
+        );
+    }
+
+    #[test]
+    fn test_write_preserving_changes_only_its_fields() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("stone.yaml");
+        fs::write(
+            &path,
+            "name: foo\nversion: \"1.0.0\" # quoted\nrelease: 7\nsummary: Foo\n",
+        )
+        .expect("Failed to write stone.yaml");
+
+        let mut manifest: Manifest = read_yaml(&path).expect("Failed to read stone.yaml");
+        manifest.version = "1.1.0".to_string();
+        manifest.release = Some(1);
+        manifest.homepage = Some("https://example.org".to_string());
+        manifest
+            .write_preserving(&path)
+            .expect("Failed to rewrite stone.yaml");
+
+        assert_eq!(
+            fs::read_to_string(&path).expect("Failed to read stone.yaml"),
+            "name: foo\nversion: \"1.1.0\" # quoted\nhomepage: https://example.org\nrelease: 1\nsummary: Foo\n"
+        );
+    }
+
+    #[test]
+    fn test_set_upstream_hash() {
+        let recipe = r#"upstreams:
+    # The release tarball
+    - https://example.org/foo-1.1.0.tar.xz: https://example.org/foo-1.1.0.tar.xz # keep
+    - https://example.org/foo-data-1.1.0.tar.xz:
+        unpack: false
+        hash: 0123abcd
+    - git|https://example.org/foo.git: 4567cdef
+"#;
+
+        let pinned = set_upstream_hash(recipe, "https://example.org/foo-1.1.0.tar.xz", "aaaa");
+        let pinned =
+            set_upstream_hash(&pinned, "https://example.org/foo-data-1.1.0.tar.xz", "bbbb");
+
+        assert_eq!(
+            pinned,
+            r#"upstreams:
+    # The release tarball
+    - https://example.org/foo-1.1.0.tar.xz: aaaa # keep
+    - https://example.org/foo-data-1.1.0.tar.xz:
+        unpack: false
+        hash: bbbb
+    - git|https://example.org/foo.git: 4567cdef
+"#
+        );
+        assert_eq!(
+            set_upstream_hash(recipe, "https://example.org/other.tar.xz", "cccc"),
+            recipe
+        );
+    }
+
+    #[test]
+    fn test_homepage_follows_upstream_when_enabled() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        fs::write(
+            path.join("stone.yaml"),
+            r#"name: foo
+version: 1.0.0
+release: 2
+homepage: https://old.example.org/foo
+upstreams:
+    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd
+"#,
+        )
+        .expect("Failed to write stone.yaml");
+        let package = Package::new(path);
+        let client = cached_client("1.1.0", Some("https://foo.example.org"));
+
+        let plan_with = |update_homepage| {
+            let config = Config {
+                update_homepage,
+                ..Config::default()
+            };
+            match package
+                .plan_update(&client, &config)
+                .expect("Planning should succeed")
+            {
+                UpdatePlan::Pending(pending) => pending,
+                plan => panic!("Expected an update, got {plan:?}"),
+            }
+        };
+
+        assert_eq!(plan_with(false).homepage, None);
+
+        let pending = plan_with(true);
+        assert_eq!(pending.homepage.as_deref(), Some("https://foo.example.org"));
+
+        write_recipe_metadata(
+            &package.manifest,
+            Some(pending.release),
+            pending.homepage.as_deref(),
+        )
+        .expect("Failed to write stone.yaml");
+        let manifest: Manifest = read_yaml(&package.manifest).expect("Failed to read stone.yaml");
+        assert_eq!(
+            manifest.homepage.as_deref(),
+            Some("https://foo.example.org")
+        );
+        assert_eq!(manifest.release, Some(1));
+    }
+
+    #[test]
+    fn test_plan_update_release() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        // Without a hash yet, the same version still needs an update to fill one in
+        fs::write(
+            path.join("stone.yaml"),
+            r#"
+name: foo
+version: 1.0.0
+release: 3
+upstreams:
+    - https://example.org/releases/foo-1.0.0.tar.xz: ""
+"#,
+        )
+        .expect("Failed to write stone.yaml");
+        let package = Package::new(path);
+
+        let release_against = |latest: &str| {
+            let client = cached_client(latest, None);
+
+            match package
+                .plan_update(&client, &Config::default())
+                .expect("Planning should succeed")
+            {
+                UpdatePlan::Pending(pending) => pending.release,
+                plan => panic!("Expected an update against {latest}, got {plan:?}"),
+            }
+        };
+
+        assert_eq!(
+            release_against("1.0.0"),
+            4,
+            "A hash-only change bumps the release"
+        );
+        assert_eq!(
+            release_against("1.1.0"),
+            1,
+            "A new version resets the release"
+        );
+    }
+
+    #[test]
+    fn test_outdated_packages() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        for (relative, version) in [("f/foo", "1.0.0"), ("b/bar", "1.1.0")] {
+            let path = write_fake_package(repo.path(), relative);
+            fs::write(
+                path.join("stone.yaml"),
+                format!(
+                    "name: pkg\nversion: {version}\nupstreams:\n    - https://example.org/pkg-{version}.tar.xz: 0123abcd\n"
+                ),
+            )
+            .expect("Failed to write stone.yaml");
+        }
+        let state = RepoState::new(Repo::Volatile(repo.path().to_path_buf()));
+
+        let outdated = state.outdated(&cached_client("1.1.0", None), &Config::default());
+
+        assert_eq!(
+            outdated,
+            [OutdatedPackage {
+                name: "foo".to_string(),
+                current_version: "1.0.0".to_string(),
+                latest_version: "1.1.0".to_string(),
+                source_type: VersionSourceType::Archive,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_running_boulder() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        let recipe = r#"
+name: foo
+version: 1.0.0
+release: 3
+upstreams:
+    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd
+"#;
+        fs::write(path.join("stone.yaml"), recipe).expect("Failed to write stone.yaml");
+        let mut package = Package::new(path);
+        let config = Config {
+            dry_run: true,
+            ..Config::default()
+        };
+
+        let outcome = package
+            .update(&cached_client("1.1.0", None), &config)
+            .expect("Dry run should succeed");
+
+        let UpdateOutcome::WouldUpdate(pending) = outcome else {
+            panic!("Expected a planned update, got {outcome:?}");
+        };
+        assert_eq!(pending.release, 1);
+        assert_eq!(
+            pending.boulder_args(&config.boulder)[..6],
+            [
+                "recipe",
+                "update",
+                "--ver",
+                "1.1.0",
+                "--upstream",
+                "https://example.org/releases/foo-1.1.0.tar.xz",
+            ]
+        );
+        // The release bump is written right before boulder is spawned, so an untouched
+        // recipe means boulder never ran
+        assert_eq!(
+            fs::read_to_string(&package.manifest).expect("Failed to read stone.yaml"),
+            recipe
+        );
+    }
+
+    #[test]
+    fn test_boulder_args_follow_config() {
+        let pending = PendingUpdate {
+            current_version: "1.0.0".to_string(),
+            latest_version: "1.1.0".to_string(),
+            current_release: Some(3),
+            release: 1,
+            current_homepage: None,
+            homepage: None,
+            upstream_args: vec![UpstreamArg::Archive {
+                url: "https://example.org/foo-1.1.0.tar.xz".to_string(),
+            }],
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let update = [
+            "recipe",
+            "update",
+            "--ver",
+            "1.1.0",
+            "--upstream",
+            "https://example.org/foo-1.1.0.tar.xz",
+            "stone.yaml",
+            "-w",
+        ];
+
+        assert_eq!(
+            pending.boulder_args(&BoulderConfig::default()),
+            [&update[..], &["--build", "--local"]].concat()
+        );
+        assert_eq!(
+            pending.boulder_args(&BoulderConfig {
+                build: false,
+                ..BoulderConfig::default()
+            }),
+            [&update[..], &["--local"]].concat()
+        );
+        assert_eq!(
+            pending.boulder_args(&BoulderConfig {
+                local: false,
+                extra_args: vec!["--jobs".to_string(), "4".to_string()],
+                ..BoulderConfig::default()
+            }),
+            [&update[..], &["--build", "--jobs", "4"]].concat()
+        );
+    }
+
+    #[test]
+    fn test_retain_matching() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        for package in ["c/cosmic-applets", "c/cosmic-term", "n/nano", "v/vim"] {
+            write_fake_package(repo.path(), package);
+        }
+        let mut state = RepoState::from_path(repo.path());
+
+        state.retain_matching(&["cosmic-*".to_string(), "nano".to_string()]);
+        let mut names: Vec<_> = state
+            .packages
+            .iter()
+            .map(|package| package.name().into_owned())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, ["cosmic-applets", "cosmic-term", "nano"]);
+    }
+
+    #[test]
+    fn test_ignored_packages_are_skipped() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        for package in ["p/python-requests", "p/python-six", "n/nano"] {
+            write_fake_package(repo.path(), package);
+        }
+        let state = RepoState::from_path(repo.path());
+        let config = Config {
+            ignore: vec!["python-*".to_string()],
+            ..Config::default()
+        };
+        // Nothing is served, so only ignored packages get through planning
+        let client = MockHttp::new(&[]);
+
+        let mut ignored: Vec<_> = state
+            .packages
+            .iter()
+            .filter(|package| {
+                package.plan_update(&client, &config).is_ok_and(|plan| {
+                    plan == UpdatePlan::Skipped {
+                        reason: "ignored".to_string(),
+                    }
+                })
+            })
+            .map(|package| package.name().into_owned())
+            .collect();
+        ignored.sort();
+
+        assert_eq!(ignored, ["python-requests", "python-six"]);
+    }
+
+    /// A client knowing release `version` of project 1 and serving its `foo` archive.
+    fn served_release(version: &str) -> MockHttp {
+        let client = MockHttp::new(&[(
+            &format!("https://example.org/releases/foo-{version}.tar.xz"),
+            &format!("foo {version} archive"),
+        )]);
+        client.release_cache().insert(1, release(version));
+
+        client
+    }
+
+    #[test]
+    fn test_update_pins_archive_checksum() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        let manifest = path.join("stone.yaml");
+        fs::write(
+            &manifest,
+            "name: foo\nversion: 1.0.0\nupstreams:\n    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd\n",
+        )
+        .expect("Failed to write stone.yaml");
+        // Stand-in for boulder moving the recipe to the new archive, with a hash of its own
+        let boulder = dir.path().join("boulder");
+        fs::write(
+            &boulder,
+            "#!/bin/sh\nsed -i 's/1\\.0\\.0.tar.xz: 0123abcd/1.1.0.tar.xz: boulders-hash/' stone.yaml\n",
+        )
+        .expect("Failed to write stub boulder");
+        fs::set_permissions(
+            &boulder,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .expect("Failed to make stub boulder executable");
+        let mut package = Package::new(path);
+        let config = Config {
+            boulder: BoulderConfig {
+                path: boulder,
+                ..BoulderConfig::default()
+            },
+            ..Config::default()
+        };
+
+        let outcome = package
+            .update(&served_release("1.1.0"), &config)
+            .expect("Update should succeed");
+
+        assert!(matches!(outcome, UpdateOutcome::Updated { .. }));
+        assert!(
+            fs::read_to_string(&package.manifest)
+                .expect("Failed to read stone.yaml")
+                .contains("- https://example.org/releases/foo-1.1.0.tar.xz: 2797530bfcc1f46a990e88348e305549fdea3dd817207c75957dad3ad8c0b79a\n")
+        );
+    }
+
+    #[test]
+    fn test_update_lands_in_one_recipe() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        fs::write(
+            path.join("stone.yaml"),
+            "name: foo\nversion: 1.0.0\nrelease: 3\nupstreams:\n    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd\n",
+        )
+        .expect("Failed to write stone.yaml");
+        // Stand-in for boulder, which edits the stone.yaml of the directory it runs in
+        let boulder = dir.path().join("boulder");
+        fs::write(
+            &boulder,
+            "#!/bin/sh\nsed -i 's/^version: 1\\.0\\.0/version: 1.1.0/; s/1\\.0\\.0.tar.xz: 0123abcd/1.1.0.tar.xz: boulders-hash/' stone.yaml\n",
+        )
+        .expect("Failed to write stub boulder");
+        fs::set_permissions(
+            &boulder,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .expect("Failed to make stub boulder executable");
+        let mut package = Package::new(path);
+        let config = Config {
+            boulder: BoulderConfig {
+                path: boulder,
+                ..BoulderConfig::default()
+            },
+            ..Config::default()
+        };
+
+        // The tests run from the crate root, not the package directory
+        let outcome = package
+            .update(&served_release("1.1.0"), &config)
+            .expect("Update should succeed");
+        let recipe = fs::read_to_string(&package.manifest).expect("Failed to read stone.yaml");
+
+        assert!(matches!(outcome, UpdateOutcome::Updated { .. }));
+        assert!(recipe.contains("version: 1.1.0\n"));
+        assert!(recipe.contains("release: 1\n"));
+        assert!(recipe.contains("- https://example.org/releases/foo-1.1.0.tar.xz: 2797530bfcc1f46a990e88348e305549fdea3dd817207c75957dad3ad8c0b79a\n"));
+        assert!(!Path::new("stone.yaml").exists());
+    }
+
+    #[test]
+    fn test_updated_package_is_not_updated_again() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        fs::write(
+            path.join("stone.yaml"),
+            "name: foo\nversion: 1.0.0\nupstreams:\n    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd\n",
+        )
+        .expect("Failed to write stone.yaml");
+        let mut package = Package::new(path);
+        // `true` stands in for a boulder run that succeeds
+        let config = Config {
+            boulder: BoulderConfig {
+                path: PathBuf::from("true"),
+                ..BoulderConfig::default()
+            },
+            ..Config::default()
+        };
+
+        let first = package
+            .update(&served_release("1.1.0"), &config)
+            .expect("Update should succeed");
+        // Nothing is served, so planning the update again would fail
+        let second = package
+            .update(&MockHttp::new(&[]), &config)
+            .expect("Second update should short-circuit");
+
+        assert!(matches!(first, UpdateOutcome::Updated { .. }));
+        assert!(package.updated);
+        assert_eq!(second, UpdateOutcome::UpToDate);
+    }
+
+    #[test]
+    fn test_missing_boulder_is_reported() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        let recipe = "name: foo\nversion: 1.0.0\nrelease: 3\nupstreams:\n    - https://example.org/releases/foo-1.0.0.tar.xz: 0123abcd\n";
+        fs::write(path.join("stone.yaml"), recipe).expect("Failed to write stone.yaml");
+        let mut package = Package::new(path);
+        let missing = dir.path().join("bin/boulder");
+        let config = Config {
+            boulder: BoulderConfig {
+                path: missing.clone(),
+                ..BoulderConfig::default()
+            },
+            ..Config::default()
+        };
+
+        let result = package.update(&served_release("1.1.0"), &config);
 
-#[derive(Debug, Clone)]
-struct UpstreamEntry {
-    url: String,
-    value: UpstreamValue,
-}
+        assert!(matches!(
+            result,
+            Err(Error::BoulderNotFound { path }) if path == missing
+        ));
+        // The release bump is undone when boulder can't be started
+        assert_eq!(
+            fs::read_to_string(&package.manifest).expect("Failed to read stone.yaml"),
+            recipe
+        );
+    }
 
-#[derive(Debug, serde::Deserialize)]
-struct Manifest {
-    name: String,
-    version: String,
-    #[serde(default)]
-    release: Option<u32>,
-    #[serde(default, deserialize_with = "deserialize_upstreams")]
-    upstreams: Option<Vec<UpstreamEntry>>,
-    #[serde(default)]
-    homepage: Option<String>,
-}
+    #[test]
+    fn test_compare_versions() {
+        // Upgrades, including ones a string comparison gets wrong
+        assert_eq!(compare_versions("1.9.0", "1.10.0"), Some(Ordering::Greater));
+        assert_eq!(compare_versions("6.1", "6.1.1"), Some(Ordering::Greater));
+        assert_eq!(
+            compare_versions("2.0.0-rc1", "2.0.0"),
+            Some(Ordering::Greater)
+        );
+        // Equal versions, however they're spelled
+        assert_eq!(compare_versions("1.2.0", "1.2"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("1.2.3", "v1.2.3"), Some(Ordering::Equal));
+        // Downgrades
+        assert_eq!(compare_versions("1.10.0", "1.9.0"), Some(Ordering::Less));
+        // Longer dotted versions are compared number by number
+        assert_eq!(
+            compare_versions("1.2.3.4", "1.2.3.5"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare_versions("1.2.3", "1.2.3.1"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(compare_versions("1.2.10", "1.2.3.4"), Some(Ordering::Less));
+        assert_eq!(compare_versions("1.2.3.0", "1.2.3"), Some(Ordering::Equal));
+        // Other schemes that aren't semver can't be ordered
+        assert_eq!(compare_versions("2024a", "2024b"), None);
+        assert_eq!(compare_versions("1.2.3.4", "1.2.4-rc1"), None);
+    }
 
-#[derive(Debug, serde::Deserialize)]
-struct ApiProject {
-    version: String,
-    homepage: Option<String>,
-}
+    #[test]
+    fn test_plan_update_follows_version_order() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let path = write_fake_package(dir.path(), "foo");
+        fs::write(
+            path.join("stone.yaml"),
+            r#"
+name: foo
+version: 1.10.0
+upstreams:
+    - https://example.org/releases/foo-1.10.0.tar.xz: 0123abcd
+"#,
+        )
+        .expect("Failed to write stone.yaml");
+        let package = Package::new(path);
 
-// Custom deserailizer for upstreams
-fn deserialize_upstreams<'de, D>(deserializer: D) -> Result<Option<Vec<UpstreamEntry>>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let raw: Option<Vec<HashMap<String, serde_yaml::Value>>> = Option::deserialize(deserializer)?;
+        let plan_against = |latest: &str| {
+            let client = cached_client(latest, None);
 
-    Ok(raw.map(|upstreams| {
-        upstreams
-            .into_iter()
-            .filter_map(|map| {
-                let (url, value) = map.into_iter().next()?;
+            package
+                .plan_update(&client, &Config::default())
+                .expect("Planning should succeed")
+        };
 
-                let upstream_value = match value {
-                    // Simple string hash
-                    serde_yaml::Value::String(hash) => {
-                        if url.starts_with("git|") {
-                            UpstreamValue::Git(hash)
-                        } else {
-                            UpstreamValue::Simple(hash)
-                        }
-                    }
-                    // Extended object with hash + properties
-                    serde_yaml::Value::Mapping(map) => {
-                        let hash = map.get("hash")?.as_str()?.to_string();
-                        let mut properties = HashMap::new();
+        match plan_against("1.11.0") {
+            UpdatePlan::Pending(pending) => assert_eq!(pending.latest_version, "1.11.0"),
+            plan => panic!("Expected an upgrade, got {plan:?}"),
+        }
+        assert!(matches!(plan_against("1.10.0"), UpdatePlan::UpToDate));
+        assert_eq!(
+            plan_against("1.9.0"),
+            UpdatePlan::Skipped {
+                reason: "upstream 1.9.0 is older than 1.10.0".to_string()
+            }
+        );
+    }
 
-                        for (k, v) in map {
-                            if let serde_yaml::Value::String(key) = k {
-                                if key != "hash" {
-                                    properties.insert(key.clone(), v.clone());
-                                }
-                            }
-                        }
+    #[test]
+    fn test_malformed_manifest_is_an_error() {
+        let dir = tempfile::tempdir().expect("Failed to create temp package");
+        let mut package = Package::new(dir.path());
+        fs::write(&package.monitoring, "releases:\n    id: 1\n")
+            .expect("Failed to write monitoring.yaml");
+        fs::write(&package.manifest, "name: [unterminated\n").expect("Failed to write stone.yaml");
 
-                        if url.starts_with("git|") {
-                            UpstreamValue::Git(hash)
-                        } else {
-                            UpstreamValue::Extended { hash, properties }
-                        }
-                    }
-                    _ => return None,
-                };
+        let result = package.update(&HttpClient::new(&Config::default()), &Config::default());
 
-                Some(UpstreamEntry {
-                    url,
-                    value: upstream_value,
-                })
-            })
-            .collect()
-    }))
-}
+        assert!(
+            matches!(&result, Err(Error::Yaml { path, .. }) if *path == package.manifest),
+            "Malformed stone.yaml should be a YAML error, got {result:?}"
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_empty_upstream_hash_still_resolves() {
+        let manifest_content = r#"
+name: foo
+version: 1.0.0
+release: 1
+upstreams:
+    - https://example.org/releases/foo-1.0.0.tar.xz: ""
+    - https://example.org/releases/foo-data-1.0.0.tar.xz:
+"#;
+        let manifest: Manifest =
+            serde_yaml::from_str(manifest_content).expect("Failed to parse manifest");
+        let upstreams = manifest.upstreams.expect("Manifest should have upstreams");
+        assert_eq!(upstreams.len(), 2, "Placeholder upstreams should be kept");
+
+        let package = Package::new("foo");
+
+        for upstream in &upstreams {
+            assert!(package.get_current_hash(upstream).is_empty());
+
+            // Same version, but the missing hash means resolution still has to happen
+            let resolved = package
+                .resolve_upstream(
+                    &HttpClient::new(&Config::default()),
+                    release(&manifest.version),
+                    upstream,
+                    &manifest.version,
+                    &Config::default(),
+                )
+                .expect("Archive upstream should resolve");
+            assert_eq!(
+                resolved.detail,
+                UpstreamDetail::ArchiveUrl {
+                    url: upstream.url.clone(),
+                    hash: None,
+                }
+            );
+        }
+    }
+
+    /// A release of `version` with nothing else known about it.
+    fn release(version: &str) -> LatestRelease {
+        LatestRelease {
+            version: version.to_string(),
+            homepage: None,
+            archive_url: None,
+            ..Default::default()
+        }
+    }
+
+    /// A client answering release-monitoring lookups for project 1 from its cache, so
+    /// nothing touches the network.
+    fn cached_client(version: &str, homepage: Option<&str>) -> HttpClient {
+        let cache = ReleaseCache::load(None, Duration::from_secs(60), false);
+        cache.insert(
+            1,
+            LatestRelease {
+                homepage: homepage.map(String::from),
+                ..release(version)
+            },
+        );
+
+        HttpClient::new(&Config::default()).with_release_cache(cache)
+    }
+
+    /// Write a fake package with both recipe files under `repo`.
+    fn write_fake_package(repo: &Path, relative: &str) -> PathBuf {
+        let dir = repo.join(relative);
+        fs::create_dir_all(&dir).expect("Failed to create package dir");
+        fs::write(dir.join("stone.yaml"), "name: pkg\nversion: 1.0.0\n")
+            .expect("Failed to write stone.yaml");
+        fs::write(dir.join("monitoring.yaml"), "releases:\n    id: 1\n")
+            .expect("Failed to write monitoring.yaml");
+
+        dir
+    }
+
+    #[test]
+    fn test_retain_changed_since() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        let edited = write_fake_package(repo.path(), "e/edited");
+        let untouched = write_fake_package(repo.path(), "u/untouched");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        for file in ["stone.yaml", "monitoring.yaml"] {
+            for package in [&edited, &untouched] {
+                fs::File::options()
+                    .write(true)
+                    .open(package.join(file))
+                    .and_then(|file| file.set_modified(an_hour_ago))
+                    .expect("Failed to backdate recipe");
+            }
+        }
+        let mut state = RepoState::from_path(repo.path());
+        let last_cycle = Instant::now() - Duration::from_secs(60);
+        fs::write(edited.join("monitoring.yaml"), "releases:\n    id: 2\n")
+            .expect("Failed to edit monitoring.yaml");
+
+        state.retain_changed_since(last_cycle);
+
+        assert_eq!(state.packages.len(), 1);
+        assert_eq!(state.packages[0].path, edited);
+    }
+
+    #[test]
+    fn test_create_volatile_repo_state() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        let foo = write_fake_package(repo.path(), "f/foo");
+        let bar = write_fake_package(repo.path(), "b/bar");
+        fs::create_dir_all(repo.path().join("n/not-a-package")).expect("Failed to create dir");
+
+        let state = RepoState::new(Repo::Volatile(repo.path().to_path_buf()));
+
+        match &state.repo_type {
+            Repo::Volatile(path) => assert_eq!(path, repo.path()),
+            repo_type => panic!("Expected a volatile repo, got {repo_type:?}"),
+        }
+
+        let mut paths: Vec<&PathBuf> = state.packages.iter().map(|package| &package.path).collect();
+        paths.sort();
+        assert_eq!(paths, [&bar, &foo]);
+
+        for package in &state.packages {
+            assert!(package.manifest.exists());
+            assert!(package.monitoring.exists());
+        }
+    }
+
+    #[test]
+    fn test_local_repo_state_skips_non_packages() {
+        let repo = tempfile::tempdir().expect("Failed to create temp repo");
+        let applets = write_fake_package(repo.path(), "c/cosmic-applets");
+        let files = write_fake_package(repo.path(), "c/cosmic-files");
+
+        // A directory with only one of the recipe files
+        let half = repo.path().join("h/half-recipe");
+        fs::create_dir_all(&half).expect("Failed to create dir");
+        fs::write(half.join("stone.yaml"), "name: half\nversion: 1.0.0\n")
+            .expect("Failed to write stone.yaml");
+
+        // Plain files and a recipe nested too deep to be a package
+        fs::write(repo.path().join("README.md"), "recipes").expect("Failed to write file");
+        write_fake_package(repo.path(), "c/cosmic-files/pkg/nested");
+
+        let state = RepoState::new(Repo::Local(repo.path().to_path_buf()));
+
+        let mut paths: Vec<&PathBuf> = state.packages.iter().map(|package| &package.path).collect();
+        paths.sort();
+        assert_eq!(paths, [&applets, &files]);
+    }
+
+    /// The sample recipes repository under `tests/fixtures/repo`.
+    fn fixture_repo() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo")
+    }
 
     #[test]
     fn test_create_default_repo_state() {
         let def_rs = RepoState::default();
 
         match def_rs.repo_type {
-            Repo::Local(local_repo) => {
-                assert_eq!(
-                    local_repo,
-                    dirs::home_dir()
-                        .expect("Failed test to get home_dir")
-                        .join("repos/aerynos/recipes")
-                );
+            Repo::Local(local_repo) => assert_eq!(local_repo, RepoType::Local.default_path()),
+            repo_type => {
+                panic!("RepoState::default() should track the local repo, got {repo_type:?}")
             }
-            _ => panic!(
-                "RepoState::default() did not default to the Repo::Local type with the local repo at /home/$USER/repos/aerynos/recipes directory"
-            ),
         }
 
-        assert!(!def_rs.packages.is_empty());
-        assert!(def_rs.last_update < std::time::Instant::now());
+        assert!(def_rs.last_update <= Instant::now());
     }
 
     #[test]
     fn test_create_local_repo_state() {
-        let local_repo = RepoState::new_local();
-
-        match local_repo.repo_type {
-            Repo::Local(repo) => {
-                assert_eq!(
-                    repo,
-                    dirs::home_dir()
-                        .expect("Failed to get home directory")
-                        .join("repos/aerynos/recipes")
-                );
-            }
-            _ => panic!(
-                "RepoState::default() did not default to the Repo::Local type with the local repo at /home/$USER/repos/aerynos"
-            ),
+        let local_repo = RepoState::from_path(fixture_repo());
+
+        match &local_repo.repo_type {
+            Repo::Local(repo) => assert_eq!(repo, &fixture_repo()),
+            repo_type => panic!("Expected a local repo, got {repo_type:?}"),
         }
 
-        assert!(!local_repo.packages.is_empty());
-        assert!(local_repo.last_update < std::time::Instant::now());
+        // The half recipe has no monitoring.yaml, so it isn't a package
+        let mut names: Vec<_> = local_repo.packages.iter().map(Package::name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "broken-manifest",
+                "broken-monitoring",
+                "cosmic-applets",
+                "nano"
+            ]
+        );
+        assert!(local_repo.last_update <= Instant::now());
     }
 
     #[test]
-    fn test_get_package_latest_info() {
-        // Create a test Package pointing to the cosmic-applets local directory
-        let cosmic_applets_path = dirs::home_dir()
-            .expect("Failed to get home directory")
-            .join("repos/aerynos/recipes/c/cosmic-applets");
-
-        let package = Package {
-            path: cosmic_applets_path.clone(),
-            manifest: cosmic_applets_path.join("stone.yaml"),
-            monitoring: cosmic_applets_path.join("monitoring.yaml"),
-            updated: false,
+    fn test_packages_iter_matches_new() {
+        let repo = Repo::Local(fixture_repo());
+
+        let first = RepoState::packages_iter(&repo)
+            .next()
+            .expect("Fixture repo has packages");
+        let mut streamed: Vec<_> = RepoState::packages_iter(&repo)
+            .map(|package| package.path)
+            .collect();
+        let mut collected: Vec<_> = RepoState::new(repo)
+            .packages
+            .into_iter()
+            .map(|package| package.path)
+            .collect();
+        streamed.sort();
+        collected.sort();
+
+        assert!(first.manifest.exists());
+        assert_eq!(streamed, collected);
+    }
+
+    #[test]
+    fn test_workspace_scans_every_repo() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let workspace = WorkspaceState::new(vec![
+            Repo::Local(fixture_repo()),
+            Repo::Volatile(fixtures.join("volatile")),
+        ]);
+
+        let mut names: Vec<_> = workspace.packages().map(Package::name).collect();
+        names.sort();
+
+        assert_eq!(workspace.repos.len(), 2);
+        assert!(matches!(workspace.repos[1].repo_type, Repo::Volatile(_)));
+        assert_eq!(
+            names,
+            [
+                "broken-manifest",
+                "broken-monitoring",
+                "cosmic-applets",
+                "nano",
+                "zlib-ng"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixture_recipes_parse() {
+        let packages = RepoState::from_path(fixture_repo()).packages;
+        let package = |name: &str| {
+            packages
+                .iter()
+                .find(|package| package.name() == name)
+                .unwrap_or_else(|| panic!("Fixture {name} should be discovered"))
         };
 
-        // Verify the files exist before testing
-        assert!(
-            package.manifest.exists(),
-            "stone.yaml file should exist for cosmic-applets"
+        for name in ["cosmic-applets", "nano"] {
+            let manifest: Manifest =
+                read_yaml(&package(name).manifest).expect("Valid fixture manifest should parse");
+            assert_eq!(manifest.name, name);
+            read_yaml::<Monitoring>(&package(name).monitoring)
+                .expect("Valid fixture monitoring should parse");
+        }
+
+        // Malformed recipes are errors rather than panics or skips
+        let client = MockHttp::new(&[]);
+        for name in ["broken-manifest", "broken-monitoring"] {
+            assert!(matches!(
+                package(name).plan_update(&client, &Config::default()),
+                Err(Error::Yaml { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_latest_version() {
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let monitored = package_with(
+            dir.path(),
+            "name: foo\nversion: 1.0.0\nupstreams:\n    - https://example.org/foo-1.0.0.tar.xz: 0123abcd\n",
+            "releases:\n    id: 4242\n",
+        );
+        let unknown_dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let unknown = package_with(
+            unknown_dir.path(),
+            "name: bar\nversion: 1.0.0\nupstreams:\n    - https://example.org/bar-1.0.0.tar.xz: 0123abcd\n",
+            "releases:\n    id: ~\n",
+        );
+        let client = MockHttp::new(&[
+            (
+                "https://release-monitoring.org/api/project/4242",
+                r#"{"version":"1.2.0","homepage":null}"#,
+            ),
+            (
+                "https://release-monitoring.org/api/projects/?pattern=bar",
+                r#"{"projects":[]}"#,
+            ),
+        ]);
+
+        assert_eq!(
+            monitored
+                .latest_version(&client, &Config::default())
+                .expect("Lookup should succeed")
+                .as_deref(),
+            Some("1.2.0")
+        );
+        assert_eq!(
+            unknown
+                .latest_version(&client, &Config::default())
+                .expect("Lookup should succeed"),
+            None
         );
+        // A failed request is an error rather than an unknown version
         assert!(
-            package.monitoring.exists(),
-            "monitoring.yaml file should exist for cosmic-applets"
+            monitored
+                .latest_version(&MockHttp::new(&[]), &Config::default())
+                .is_err()
         );
+    }
+
+    #[test]
+    fn test_load_is_reused_until_files_change() {
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = Package::new(write_fake_package(dir.path(), "n/nano"));
 
-        // Read and parse the monitoring file to create a Monitoring struct
-        let monitoring_content = fs::read_to_string(&package.monitoring)
-            .expect("Should be able to read monitoring.yaml");
-        let monitoring: Monitoring = serde_yaml::from_str(&monitoring_content)
-            .expect("Should be able to parse monitoring.yaml");
+        let first = package.load().expect("Fake package should load");
+        let again = package.load().expect("Fake package should load");
+        fs::write(&package.manifest, "name: nano\nversion: 8.10.0\n")
+            .expect("Failed to write stone.yaml");
+        let changed = package.load().expect("Rewritten package should load");
 
-        let manifest_content =
-            fs::read_to_string(&package.manifest).expect("Should be able to read stone.yaml");
-        let manifest: Manifest =
-            serde_yaml::from_str(&manifest_content).expect("Failed to parse content of stone.yaml");
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert_eq!(changed.version(), "8.10.0");
+    }
+
+    #[test]
+    fn test_get_package_latest_info() {
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let path = write_fake_package(dir.path(), "c/cosmic-applets");
+        fs::write(path.join("monitoring.yaml"), "releases:\n    id: 377113\n")
+            .expect("Failed to write monitoring.yaml");
+        fs::write(
+            path.join("stone.yaml"),
+            r#"
+name: cosmic-applets
+version: 1.0.0
+upstreams:
+    - git|https://github.com/pop-os/cosmic-applets.git: 0123abcd
+"#,
+        )
+        .expect("Failed to write stone.yaml");
+        let package = Package::new(path);
+        let client = MockHttp::new(&[
+            (
+                "https://release-monitoring.org/api/project/377113",
+                r#"{"version":"1.0.1","homepage":"https://github.com/pop-os/cosmic-applets"}"#,
+            ),
+            (
+                "https://api.github.com/repos/pop-os/cosmic-applets/tags",
+                r#"[
+                    {"name":"epoch-1.0.1","commit":{"sha":"ffffffffffffffffffffffffffffffffffffffff"}},
+                    {"name":"1.0.1","commit":{"sha":"1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d"}}
+                ]"#,
+            ),
+        ]);
 
-        // Test get_latest
-        if let Some(first_upstream) = manifest
+        let loaded = package
+            .load()
+            .expect("Should be able to parse the package's files");
+        let LoadedPackage {
+            manifest,
+            monitoring,
+            ..
+        } = &*loaded;
+        let first_upstream = manifest
             .upstreams
             .as_ref()
             .and_then(|upstream| upstream.first())
-        {
-            let (latest_vers, latest_hash) =
-                package.get_latest(&monitoring, first_upstream, &manifest.version);
+            .expect("cosmic-applets should have an upstream");
 
-            // Assertions
-            assert!(
-                !latest_vers.is_empty(),
-                "Latest version should not be empty"
-            );
+        let latest = package
+            .get_latest(
+                &client,
+                monitoring,
+                &manifest.name,
+                first_upstream,
+                &manifest.version,
+                &Config::default(),
+            )
+            .expect("Latest upstream should resolve for cosmic-applets");
 
-            // For cosmic-applets, it's expected to be a GitHub project, so a hash should be found
-            assert!(
-                !latest_hash.is_empty(),
-                "Latest hash should not be empty for cosmic-applets"
-            );
+        assert_eq!(monitoring.releases.id, Some(377113));
+        assert_eq!(
+            latest,
+            ResolvedUpstream {
+                version: "1.0.1".to_string(),
+                // The tag named after the version wins over the newest tag
+                detail: UpstreamDetail::GitHash(
+                    "1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d".to_string()
+                ),
+                homepage: Some("https://github.com/pop-os/cosmic-applets".to_string()),
+            }
+        );
+    }
 
-            // Verify that monitoring ID is correct for cosmic-applets (377113)
-            assert_eq!(
-                monitoring.releases.id,
-                Some(377113),
-                "cosmic-applets should have a monitoring ID 377113"
+    // Whole plan_update runs against a local server standing in for release-monitoring.org
+    // and GitHub, through the real HTTP client
+
+    /// Sends the requests meant for release-monitoring.org and GitHub to the test server at
+    /// `base` instead.
+    struct Rebased {
+        client: HttpClient,
+        base: String,
+    }
+
+    impl Rebased {
+        fn new(base: String) -> Self {
+            let client = HttpClient {
+                backoff: Duration::from_millis(1),
+                ..HttpClient::new(&Config {
+                    request_interval_ms: 0,
+                    ..Config::default()
+                })
+            };
+
+            Self {
+                client: client.with_release_cache(ReleaseCache::load(
+                    None,
+                    Duration::from_secs(60),
+                    false,
+                )),
+                base,
+            }
+        }
+    }
+
+    impl HttpGet for Rebased {
+        fn get_text(&self, url: &str) -> Result<String, ResolveError> {
+            let url = [RELEASE_MONITORING_API, GITHUB_API]
+                .iter()
+                .find_map(|api| url.strip_prefix(api))
+                .map_or_else(|| url.to_string(), |path| format!("{}{path}", self.base));
+
+            self.client.get_text(&url)
+        }
+
+        fn download(&self, url: &str, sink: &mut dyn Write) -> Result<Download, ResolveError> {
+            self.client.download(url, sink)
+        }
+
+        fn release_cache(&self) -> &ReleaseCache {
+            self.client.release_cache()
+        }
+    }
+
+    /// A package in `dir` with the given recipe and monitoring file contents.
+    fn package_with(dir: &Path, stone: &str, monitoring: &str) -> Package {
+        let path = write_fake_package(dir, "foo");
+        fs::write(path.join("stone.yaml"), stone).expect("Failed to write stone.yaml");
+        fs::write(path.join("monitoring.yaml"), monitoring)
+            .expect("Failed to write monitoring.yaml");
+
+        Package::new(path)
+    }
+
+    fn pending(plan: UpdatePlan) -> PendingUpdate {
+        match plan {
+            UpdatePlan::Pending(pending) => pending,
+            plan => panic!("Expected a pending update, got {plan:?}"),
+        }
+    }
+
+    #[test]
+    fn test_flow_git_source_resolves_tag_hash() {
+        let (base, server) = serve(&[
+            ("200 OK", r#"{"version":"1.3.0","homepage":null}"#),
+            (
+                "200 OK",
+                r#"[{"name":"v1.3.0","commit":{"sha":"1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d"}}]"#,
+            ),
+        ]);
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 1.2.0\nupstreams:\n    - git|https://github.com/example/foo.git: 0123abcd\n",
+            "releases:\n    id: 4242\n",
+        );
+
+        let plan = package
+            .plan_update(&Rebased::new(base), &Config::default())
+            .expect("Planning should succeed");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /project/4242 "));
+        assert!(requests[1].starts_with("GET /repos/example/foo/tags "));
+        assert_eq!(
+            pending(plan).boulder_args(&BoulderConfig::default())[3..6],
+            [
+                "1.3.0",
+                "--upstream",
+                "https://github.com/example/foo.git, 1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flow_archive_source_replaces_version() {
+        let (base, server) = serve(&[("200 OK", r#"{"version":"2.0.1","homepage":null}"#)]);
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 2.0.0\nupstreams:\n    - https://example.org/foo/2.0.0/foo-2.0.0.tar.xz: 0123abcd\n",
+            "releases:\n    id: 4242\n",
+        );
+
+        let plan = package
+            .plan_update(&Rebased::new(base), &Config::default())
+            .expect("Planning should succeed");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(
+            pending(plan).boulder_args(&BoulderConfig::default())[3..6],
+            [
+                "2.0.1",
+                "--upstream",
+                "https://example.org/foo/2.0.1/foo-2.0.1.tar.xz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flow_every_upstream_is_refreshed() {
+        let (base, server) = serve(&[("200 OK", r#"{"version":"2.0.1","homepage":null}"#)]);
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 2.0.0\nupstreams:\n    - https://example.org/foo/2.0.0/foo-2.0.0.tar.xz: 0123abcd\n    - https://example.org/foo/2.0.0/foo-data-2.0.0.tar.xz: 4567cdef\n",
+            "releases:\n    id: 4242\n",
+        );
+
+        let plan = package
+            .plan_update(&Rebased::new(base), &Config::default())
+            .expect("Planning should succeed");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(
+            pending(plan).boulder_args(&BoulderConfig::default())[3..8],
+            [
+                "2.0.1",
+                "--upstream",
+                "https://example.org/foo/2.0.1/foo-2.0.1.tar.xz",
+                "--upstream",
+                "https://example.org/foo/2.0.1/foo-data-2.0.1.tar.xz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flow_rate_limited_lookup_is_retried() {
+        let (base, server) = serve(&[
+            ("429 Too Many Requests\r\nRetry-After: 0", ""),
+            ("200 OK", r#"{"version":"2.0.1","homepage":null}"#),
+        ]);
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 2.0.0\nupstreams:\n    - https://example.org/foo-2.0.0.tar.xz: 0123abcd\n",
+            "releases:\n    id: 4242\n",
+        );
+
+        let plan = package
+            .plan_update(&Rebased::new(base), &Config::default())
+            .expect("Planning should succeed");
+        let requests = server.join().expect("Test server panicked");
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(pending(plan).latest_version, "2.0.1");
+    }
+
+    #[test]
+    fn test_flow_exhausted_retries_are_skipped() {
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 2.0.0\nupstreams:\n    - https://example.org/foo-2.0.0.tar.xz: 0123abcd\n",
+            "releases:\n    id: 4242\n",
+        );
+
+        for status in [
+            "429 Too Many Requests\r\nRetry-After: 0",
+            "503 Service Unavailable",
+        ] {
+            // The first answer and all three retries are turned away
+            let (base, server) = serve(&[(status, ""); 4]);
+            let plan = package
+                .plan_update(&Rebased::new(base.clone()), &Config::default())
+                .expect("Planning should succeed");
+            let requests = server.join().expect("Test server panicked");
+
+            assert_eq!(requests.len(), 4);
+            assert!(
+                matches!(
+                    &plan,
+                    UpdatePlan::Skipped { reason }
+                        if reason.starts_with(&format!("gave up on {base}/project/4242 after retrying"))
+                ),
+                "{status} should skip the package, got {plan:?}"
             );
         }
+
+        // `check` and `outdated` look the version up without planning an update
+        let (base, server) = serve(&[("503 Service Unavailable", ""); 4]);
+        let latest = package.latest_version(&Rebased::new(base), &Config::default());
+        server.join().expect("Test server panicked");
+
+        assert!(
+            matches!(latest, Ok(None)),
+            "Exhausted retries should leave the version unknown, got {latest:?}"
+        );
+    }
+
+    #[test]
+    fn test_flow_unknown_project_is_skipped() {
+        let (base, server) = serve(&[("200 OK", r#"{"projects":[]}"#)]);
+        let dir = tempfile::tempdir().expect("Failed to create temp repo");
+        let package = package_with(
+            dir.path(),
+            "name: foo\nversion: 2.0.0\nupstreams:\n    - https://example.org/foo-2.0.0.tar.xz: 0123abcd\n",
+            "releases: {}\n",
+        );
+
+        let plan = package
+            .plan_update(&Rebased::new(base), &Config::default())
+            .expect("Planning should succeed");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /projects/?pattern=foo "));
+        assert_eq!(
+            plan,
+            UpdatePlan::Skipped {
+                reason: "no release-monitoring id".to_string()
+            }
+        );
     }
 }
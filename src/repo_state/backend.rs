@@ -0,0 +1,1002 @@
+use super::{
+    GitForge, GitUrl, HttpGet, Package, UpstreamEntry, compare_versions, get_json, parse_version,
+};
+use crate::config::Config;
+use crate::{cache::LatestRelease, error::ResolveError};
+use regex::Regex;
+use std::{cmp::Ordering, collections::HashMap};
+
+/// Base url of the release-monitoring.org API.
+pub const RELEASE_MONITORING_API: &str = "https://release-monitoring.org/api";
+
+/// Base url of the PyPI JSON API.
+pub const PYPI_API: &str = "https://pypi.org/pypi";
+
+/// Base url of the crates.io API.
+pub const CRATES_IO_API: &str = "https://crates.io/api/v1";
+
+/// A source of upstream releases, picked per package from its monitoring file.
+pub trait VersionBackend {
+    /// The latest release of `package`, whose current source is `upstream`.
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        package: &Package,
+        upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError>;
+}
+
+/// Which upstream versions a package may move to.
+#[derive(Debug, Clone, Default)]
+pub struct VersionFilter {
+    /// Leave out versions marked as pre-releases.
+    pub stable_only: bool,
+    /// Leave out versions matching any of these, such as CI tags or `99.0` marker releases.
+    pub ignore_versions: Vec<Regex>,
+}
+
+impl VersionFilter {
+    /// The filter of a package whose monitoring file sets `stable_only` and
+    /// `ignore_version_regex`, if it does. `stable_only` falls back to `config`'s, while
+    /// `config`'s `ignore_version_regex` applies on top of the package's.
+    pub fn new(
+        stable_only: Option<bool>,
+        ignore_version_regex: Option<&str>,
+        config: &Config,
+    ) -> Result<Self, ResolveError> {
+        let ignore_versions = [ignore_version_regex, config.ignore_version_regex.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|source| ResolveError::InvalidVersionRegex {
+                    pattern: pattern.to_string(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            stable_only: stable_only.unwrap_or(config.stable_only),
+            ignore_versions,
+        })
+    }
+
+    /// Whether a package may move to `version`.
+    pub fn allows(&self, version: &str) -> bool {
+        let prerelease = self.stable_only && is_prerelease(version);
+        let ignored = self
+            .ignore_versions
+            .iter()
+            .any(|pattern| pattern.is_match(version));
+
+        !(prerelease || ignored)
+    }
+}
+
+/// release-monitoring.org's project API, cached between runs.
+#[derive(Debug, Clone)]
+pub struct ReleaseMonitoring {
+    pub api_base: String,
+    pub id: Option<u32>,
+    /// The project name to search for when there is no `id`.
+    pub name: String,
+    /// Pick among all versions rather than only the stable ones.
+    pub prereleases: bool,
+    /// Versions ruled out before the highest is picked.
+    pub filter: VersionFilter,
+}
+
+/// The latest published release of the GitHub repository the upstream is hosted in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHubReleases {
+    pub api_base: String,
+}
+
+/// The newest stable release of a PyPI project that has a source distribution and isn't
+/// yanked, along with that sdist.
+#[derive(Debug, Clone)]
+pub struct PyPI {
+    pub api_base: String,
+    pub project: String,
+    /// Versions ruled out before the highest is picked.
+    pub filter: VersionFilter,
+}
+
+/// The newest stable, non-yanked version of a crate on crates.io, along with its crate
+/// download.
+#[derive(Debug, Clone)]
+pub struct CratesIo {
+    pub api_base: String,
+    pub name: String,
+    /// Versions ruled out before the highest is picked.
+    pub filter: VersionFilter,
+}
+
+/// The newest entry of an RSS or Atom release feed, for upstreams release-monitoring doesn't
+/// know about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RssFeed {
+    pub url: String,
+}
+
+impl VersionBackend for ReleaseMonitoring {
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        _package: &Package,
+        _upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct ApiProject {
+            version: String,
+            homepage: Option<String>,
+            #[serde(default)]
+            versions: Vec<String>,
+            #[serde(default)]
+            stable_versions: Vec<String>,
+        }
+
+        let id = match self.id {
+            Some(id) => id,
+            None => self.find_id(client)?,
+        };
+        let release = match client.release_cache().get(id) {
+            Some(release) => release,
+            None => {
+                let url = format!("{}/project/{id}", self.api_base);
+                let project: ApiProject = get_json(client, &url)?;
+
+                let release = LatestRelease {
+                    version: project.version,
+                    homepage: project.homepage,
+                    archive_url: None,
+                    versions: project.versions,
+                    stable_versions: project.stable_versions,
+                };
+                client.release_cache().insert(id, release.clone());
+                release
+            }
+        };
+
+        // `version` is simply the newest release, which can be a beta or a release candidate
+        let listed = if self.prereleases || release.stable_versions.is_empty() {
+            &release.versions
+        } else {
+            &release.stable_versions
+        };
+        let candidates: Vec<String> = listed
+            .iter()
+            .filter(|version| self.filter.allows(version))
+            .cloned()
+            .collect();
+
+        Ok(match highest_version(&candidates) {
+            Some(highest) => LatestRelease {
+                version: highest.clone(),
+                ..release
+            },
+            None => release,
+        })
+    }
+}
+
+impl ReleaseMonitoring {
+    /// The id of the one release-monitoring project called `name`.
+    fn find_id(&self, client: &dyn HttpGet) -> Result<u32, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct ApiProjects {
+            projects: Vec<ApiProjectId>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct ApiProjectId {
+            id: u32,
+            name: String,
+        }
+
+        // Names like `gtk+` or `c++utilities` have to be encoded to be searched for as-is
+        let mut url = reqwest::Url::parse(&format!("{}/projects/", self.api_base))
+            .expect("API base is a valid url");
+        url.query_pairs_mut().append_pair("pattern", &self.name);
+        let found: ApiProjects = get_json(client, url.as_str())?;
+
+        // The same name can be tracked once per ecosystem, guessing could pick the wrong one
+        let ids: Vec<u32> = found
+            .projects
+            .iter()
+            .filter(|project| project.name.eq_ignore_ascii_case(&self.name))
+            .map(|project| project.id)
+            .collect();
+        match ids[..] {
+            [id] => Ok(id),
+            [] => Err(ResolveError::NoMonitoringId),
+            _ => Err(ResolveError::AmbiguousProject {
+                name: self.name.clone(),
+                matches: ids.len(),
+            }),
+        }
+    }
+}
+
+impl VersionBackend for GitHubReleases {
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        package: &Package,
+        upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct GitHubRelease {
+            tag_name: String,
+        }
+
+        let clean_url = GitUrl::parse(&upstream.url).clone_url;
+        let Some(GitForge::GitHub { owner, repo }) = GitForge::from_url(clean_url) else {
+            eprintln!(
+                "The github backend needs a GitHub upstream for {}",
+                package.path.display()
+            );
+            return Err(ResolveError::NoHashOrUrl);
+        };
+
+        let url = format!("{}/repos/{owner}/{repo}/releases/latest", self.api_base);
+        let release: GitHubRelease = get_json(client, &url)?;
+
+        // Tags name versions as `v1.2.3`, `foo-1.2.3`, `release-1.2.3`, ...
+        let version = release
+            .tag_name
+            .trim_start_matches(|c: char| !c.is_ascii_digit());
+
+        Ok(LatestRelease {
+            version: version.to_string(),
+            homepage: None,
+            archive_url: None,
+            ..Default::default()
+        })
+    }
+}
+
+impl VersionBackend for PyPI {
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        _package: &Package,
+        _upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct PyPIProject {
+            info: PyPIInfo,
+            #[serde(default)]
+            releases: HashMap<String, Vec<PyPIFile>>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct PyPIInfo {
+            version: String,
+            home_page: Option<String>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct PyPIFile {
+            packagetype: String,
+            url: String,
+            #[serde(default)]
+            yanked: bool,
+        }
+
+        let url = format!("{}/{}/json", self.api_base, self.project);
+        let project: PyPIProject = get_json(client, &url)?;
+
+        let sdist = |files: &[PyPIFile]| {
+            files
+                .iter()
+                .find(|file| file.packagetype == "sdist" && !file.yanked)
+                .map(|file| file.url.clone())
+        };
+        let candidates: Vec<String> = project
+            .releases
+            .iter()
+            .filter(|(version, files)| {
+                is_stable(version) && self.filter.allows(version) && sdist(files).is_some()
+            })
+            .map(|(version, _)| version.clone())
+            .collect();
+
+        let (version, archive_url) = match highest_version(&candidates) {
+            Some(version) => (version.clone(), sdist(&project.releases[version])),
+            None => (project.info.version, None),
+        };
+
+        Ok(LatestRelease {
+            version,
+            homepage: project
+                .info
+                .home_page
+                .filter(|homepage| !homepage.is_empty()),
+            archive_url,
+            ..Default::default()
+        })
+    }
+}
+
+impl VersionBackend for CratesIo {
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        _package: &Package,
+        _upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError> {
+        #[derive(Debug, serde::Deserialize)]
+        struct CratesIoResponse {
+            #[serde(rename = "crate")]
+            krate: CratesIoCrate,
+            #[serde(default)]
+            versions: Vec<CratesIoVersion>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct CratesIoCrate {
+            max_stable_version: Option<String>,
+            homepage: Option<String>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct CratesIoVersion {
+            num: String,
+            #[serde(default)]
+            yanked: bool,
+        }
+
+        let (api_base, name) = (&self.api_base, &self.name);
+        let url = format!("{api_base}/crates/{name}");
+        let response: CratesIoResponse = get_json(client, &url)?;
+
+        let candidates: Vec<String> = response
+            .versions
+            .into_iter()
+            .filter(|version| {
+                !version.yanked && is_stable(&version.num) && self.filter.allows(&version.num)
+            })
+            .map(|version| version.num)
+            .collect();
+        let version = highest_version(&candidates)
+            .cloned()
+            .or(response.krate.max_stable_version)
+            .ok_or(ResolveError::NoHashOrUrl)?;
+
+        Ok(LatestRelease {
+            archive_url: Some(format!("{api_base}/crates/{name}/{version}/download")),
+            version,
+            homepage: response.krate.homepage,
+            ..Default::default()
+        })
+    }
+}
+
+impl VersionBackend for RssFeed {
+    fn latest(
+        &self,
+        client: &dyn HttpGet,
+        _package: &Package,
+        _upstream: &UpstreamEntry,
+    ) -> Result<LatestRelease, ResolveError> {
+        let feed = client.get_text(&self.url)?;
+
+        let version = latest_feed_version(&feed).ok_or_else(|| ResolveError::NoFeedVersion {
+            url: self.url.clone(),
+        })?;
+
+        Ok(LatestRelease {
+            version,
+            homepage: None,
+            archive_url: None,
+            ..Default::default()
+        })
+    }
+}
+
+/// The version in the title of the first `<item>` (RSS) or `<entry>` (Atom) of `feed`.
+///
+/// Feeds list their newest release first. Titles name it as `v1.2.3`, `Release 1.2.3`,
+/// `foo 1.2.3 released`, ..., so the first dotted number in the title is taken.
+fn latest_feed_version(feed: &str) -> Option<String> {
+    let entry = Regex::new(r"(?s)<(?:item|entry)[\s>].*?<title[^>]*>(.*?)</title>")
+        .expect("Feed entry pattern is valid");
+    let version = Regex::new(r"\d+(?:\.\d+)+").expect("Version pattern is valid");
+
+    let title = entry.captures(feed)?.get(1)?.as_str();
+    let title = title
+        .trim()
+        .strip_prefix("<![CDATA[")
+        .and_then(|title| title.strip_suffix("]]>"))
+        .unwrap_or(title);
+
+    version
+        .find(title)
+        .map(|version| version.as_str().to_string())
+}
+
+/// The highest of `versions`, listed newest first the way release-monitoring does, ordered
+/// like `plan_update` orders versions. Versions that can't be ordered only win when none can,
+/// the newest of them then.
+fn highest_version(versions: &[String]) -> Option<&String> {
+    versions
+        .iter()
+        .rev()
+        .filter(|version| compare_versions("0", version).is_some())
+        .max_by(|a, b| compare_versions(b, a).unwrap_or(Ordering::Equal))
+        .or_else(|| versions.first())
+}
+
+/// Whether `version` is one semver can order and isn't a pre-release of, which is all PyPI
+/// and crates.io releases are picked from.
+fn is_stable(version: &str) -> bool {
+    parse_version(version).is_some_and(|parsed| parsed.pre.is_empty())
+}
+
+/// Whether `version` is marked as a pre-release, e.g. `2.0.0rc1`, `1.4-beta2` or
+/// `3.1.0.dev4`.
+fn is_prerelease(version: &str) -> bool {
+    Regex::new(r"(?i)alpha|beta|rc|-pre|\.dev")
+        .expect("Pre-release pattern is valid")
+        .is_match(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::ReleaseCache,
+        config::Config,
+        repo_state::{HttpClient, UpstreamValue, tests::serve},
+    };
+    use std::time::Duration;
+
+    fn package(name: &str) -> Package {
+        Package::new(name)
+    }
+
+    fn archive_upstream(url: &str) -> UpstreamEntry {
+        UpstreamEntry {
+            url: url.to_string(),
+            value: UpstreamValue::Simple("0123abcd".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_release_monitoring_backend_caches() {
+        let (base, server) = serve(&[(
+            "200 OK",
+            r#"{"version":"2.4.1","homepage":"https://example.org"}"#,
+        )]);
+        let client = HttpClient::new(&Config::default()).with_release_cache(ReleaseCache::load(
+            None,
+            Duration::from_secs(60),
+            false,
+        ));
+        let backend = ReleaseMonitoring {
+            api_base: base,
+            id: Some(4242),
+            name: "foo".to_string(),
+            prereleases: false,
+            filter: VersionFilter::default(),
+        };
+        let upstream = archive_upstream("https://example.org/foo-2.4.0.tar.gz");
+
+        let first = backend
+            .latest(&client, &package("foo"), &upstream)
+            .expect("release-monitoring release should resolve");
+        // The server only answers once, the second lookup has to come from the cache
+        let second = backend
+            .latest(&client, &package("foo"), &upstream)
+            .expect("Cached release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("GET /project/4242 "));
+        assert_eq!(first.version, "2.4.1");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_release_monitoring_backend_prefers_stable_versions() {
+        let project = r#"{"version":"2.0.0-rc1","homepage":"https://example.org","versions":["2.0.0-rc1","1.10.0","1.9.2"],"stable_versions":["1.9.2","1.10.0"]}"#;
+        let (base, server) = serve(&[("200 OK", project), ("200 OK", project)]);
+        let client = HttpClient::new(&Config::default());
+        let backend = |prereleases| ReleaseMonitoring {
+            api_base: base.clone(),
+            id: Some(4242),
+            name: "foo".to_string(),
+            prereleases,
+            filter: VersionFilter::default(),
+        };
+        let upstream = archive_upstream("https://example.org/foo-1.9.2.tar.gz");
+
+        let stable = backend(false)
+            .latest(&client, &package("foo"), &upstream)
+            .expect("release-monitoring release should resolve");
+        let prerelease = backend(true)
+            .latest(&client, &package("foo"), &upstream)
+            .expect("release-monitoring release should resolve");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(stable.version, "1.10.0");
+        assert_eq!(prerelease.version, "2.0.0-rc1");
+    }
+
+    #[test]
+    fn test_stable_only_filters_candidates() {
+        let project = r#"{"version":"2.1.0-beta.1","homepage":"https://example.org","versions":["2.1.0-beta.1","2.0.0rc1","2.0.0","1.9.0"],"stable_versions":[]}"#;
+        let (base, server) = serve(&[("200 OK", project), ("200 OK", project)]);
+        let client = HttpClient::new(&Config::default());
+        let backend = |stable_only| ReleaseMonitoring {
+            api_base: base.clone(),
+            id: Some(4242),
+            name: "foo".to_string(),
+            prereleases: false,
+            filter: VersionFilter {
+                stable_only,
+                ..Default::default()
+            },
+        };
+        let upstream = archive_upstream("https://example.org/foo-1.9.0.tar.gz");
+
+        let stable = backend(true)
+            .latest(&client, &package("foo"), &upstream)
+            .expect("release-monitoring release should resolve");
+        let any = backend(false)
+            .latest(&client, &package("foo"), &upstream)
+            .expect("release-monitoring release should resolve");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(stable.version, "2.0.0");
+        assert_eq!(any.version, "2.1.0-beta.1");
+
+        let filter = VersionFilter {
+            stable_only: true,
+            ..Default::default()
+        };
+        assert!(!filter.allows("2.0.0rc1"));
+        assert!(!filter.allows("1.4-beta2"));
+        assert!(!filter.allows("3.0.0-pre"));
+        assert!(!filter.allows("3.1.0.dev4"));
+        assert!(filter.allows("2.0.0"));
+        assert!(VersionFilter::default().allows("2.0.0rc1"));
+    }
+
+    #[test]
+    fn test_ignored_versions_fall_through() {
+        let (base, server) = serve(&[(
+            "200 OK",
+            r#"{"version":"99.0","homepage":"https://example.org","versions":["99.0","2.4.1","2.4.0"],"stable_versions":["99.0","2.4.1","2.4.0"]}"#,
+        )]);
+        let config = Config {
+            ignore_version_regex: Some("^ci-".to_string()),
+            ..Config::default()
+        };
+        let filter =
+            VersionFilter::new(None, Some(r"^99\."), &config).expect("Version patterns are valid");
+        let backend = ReleaseMonitoring {
+            api_base: base,
+            id: Some(4242),
+            name: "foo".to_string(),
+            prereleases: false,
+            filter: filter.clone(),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&config),
+                &package("foo"),
+                &archive_upstream("https://example.org/foo-2.4.0.tar.gz"),
+            )
+            .expect("release-monitoring release should resolve");
+        server.join().expect("Test server panicked");
+
+        // The top version is ignored, so the next highest is picked
+        assert_eq!(release.version, "2.4.1");
+        assert!(!filter.allows("ci-20261016"));
+        assert!(matches!(
+            VersionFilter::new(None, Some("(unclosed"), &config),
+            Err(ResolveError::InvalidVersionRegex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_highest_version() {
+        let versions =
+            |versions: &[&str]| versions.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            highest_version(&versions(&["1.9.2", "1.10.0", "1.2"])).map(String::as_str),
+            Some("1.10.0")
+        );
+        assert_eq!(
+            highest_version(&versions(&["2024b", "2024a"])).map(String::as_str),
+            Some("2024b")
+        );
+        // Four-number versions are ordered among the three-number ones, not below them all
+        assert_eq!(
+            highest_version(&versions(&["1.2.3", "1.2.3.1", "1.2.2.9"])).map(String::as_str),
+            Some("1.2.3.1")
+        );
+        assert_eq!(
+            highest_version(&versions(&["1.2.3.4", "1.2.10", "1.2.3.10"])).map(String::as_str),
+            Some("1.2.10")
+        );
+        assert_eq!(highest_version(&[]), None);
+    }
+
+    #[test]
+    fn test_release_monitoring_backend_finds_id_by_name() {
+        let (base, server) = serve(&[
+            (
+                "200 OK",
+                r#"{"items_per_page":25,"page":1,"total_items":1,"projects":[{"id":4242,"name":"foo","homepage":"https://example.org","ecosystem":"https://example.org"}]}"#,
+            ),
+            (
+                "200 OK",
+                r#"{"version":"2.4.1","homepage":"https://example.org"}"#,
+            ),
+        ]);
+        let backend = ReleaseMonitoring {
+            api_base: base,
+            id: None,
+            name: "foo".to_string(),
+            prereleases: false,
+            filter: VersionFilter::default(),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("foo"),
+                &archive_upstream("https://example.org/foo-2.4.0.tar.gz"),
+            )
+            .expect("release-monitoring release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /projects/?pattern=foo "));
+        assert!(requests[1].starts_with("GET /project/4242 "));
+        assert_eq!(release.version, "2.4.1");
+    }
+
+    #[test]
+    fn test_release_monitoring_backend_encodes_searched_names() {
+        let (base, server) = serve(&[
+            ("200 OK", r#"{"projects":[{"id":4242,"name":"gtk+"}]}"#),
+            ("200 OK", r#"{"version":"3.24.43","homepage":null}"#),
+        ]);
+        let backend = ReleaseMonitoring {
+            api_base: base,
+            id: None,
+            name: "gtk+".to_string(),
+            prereleases: false,
+            filter: VersionFilter::default(),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("gtk+"),
+                &archive_upstream("https://example.org/gtk+-3.24.42.tar.xz"),
+            )
+            .expect("release-monitoring release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        // A bare `+` would be searched for as a space
+        assert!(requests[0].starts_with("GET /projects/?pattern=gtk%2B "));
+        assert_eq!(release.version, "3.24.43");
+    }
+
+    #[test]
+    fn test_release_monitoring_backend_skips_ambiguous_names() {
+        let (base, server) = serve(&[(
+            "200 OK",
+            r#"{"projects":[{"id":1,"name":"foo"},{"id":2,"name":"Foo"},{"id":3,"name":"foo-bar"}]}"#,
+        )]);
+        let backend = ReleaseMonitoring {
+            api_base: base,
+            id: None,
+            name: "foo".to_string(),
+            prereleases: false,
+            filter: VersionFilter::default(),
+        };
+
+        let result = backend.latest(
+            &HttpClient::new(&Config::default()),
+            &package("foo"),
+            &archive_upstream("https://example.org/foo-1.0.tar.gz"),
+        );
+        server.join().expect("Test server panicked");
+
+        assert!(matches!(
+            result,
+            Err(ResolveError::AmbiguousProject { matches: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_github_backend_release() {
+        let (base, server) = serve(&[("200 OK", r#"{"tag_name":"v1.4.0","name":"1.4.0"}"#)]);
+        let upstream = archive_upstream(
+            "https://github.com/pop-os/cosmic-applets/archive/refs/tags/v1.3.0.tar.gz",
+        );
+
+        let release = GitHubReleases { api_base: base }
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("cosmic-applets"),
+                &upstream,
+            )
+            .expect("GitHub release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /repos/pop-os/cosmic-applets/releases/latest "));
+        assert_eq!(release.version, "1.4.0");
+    }
+
+    #[test]
+    fn test_pypi_backend_release() {
+        // 2.33.0 was yanked, 3.0.0rc1 is a pre-release and 2.32.4 only has a wheel
+        let payload = r#"{
+            "info": {"name": "requests", "version": "2.33.0", "home_page": ""},
+            "releases": {
+                "2.32.3": [
+                    {"packagetype": "bdist_wheel", "url": "https://files.example/requests-2.32.3-py3-none-any.whl", "yanked": false},
+                    {"packagetype": "sdist", "url": "https://files.example/requests-2.32.3.tar.gz", "yanked": false}
+                ],
+                "2.32.4": [
+                    {"packagetype": "bdist_wheel", "url": "https://files.example/requests-2.32.4-py3-none-any.whl", "yanked": false}
+                ],
+                "2.33.0": [
+                    {"packagetype": "sdist", "url": "https://files.example/requests-2.33.0.tar.gz", "yanked": true}
+                ],
+                "3.0.0rc1": [
+                    {"packagetype": "sdist", "url": "https://files.example/requests-3.0.0rc1.tar.gz", "yanked": false}
+                ]
+            }
+        }"#;
+        let (base, server) = serve(&[("200 OK", payload)]);
+        let backend = PyPI {
+            api_base: base,
+            project: "requests".to_string(),
+            filter: VersionFilter::default(),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("python-requests"),
+                &archive_upstream("https://files.example/ab/cd/requests-2.31.0.tar.gz"),
+            )
+            .expect("PyPI release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /requests/json "));
+        assert_eq!(release.version, "2.32.3");
+        assert_eq!(release.homepage, None);
+        assert_eq!(
+            release.archive_url.as_deref(),
+            Some("https://files.example/requests-2.32.3.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_crates_io_backend_release() {
+        // 1.5.0 was yanked and 2.0.0-beta.1 is a pre-release
+        let payload = r#"{
+            "crate": {"name": "cosmic-text", "max_stable_version": "1.5.0", "homepage": null},
+            "versions": [
+                {"num": "2.0.0-beta.1", "yanked": false},
+                {"num": "1.5.0", "yanked": true},
+                {"num": "1.4.2", "yanked": false},
+                {"num": "1.10.0-rc.1", "yanked": false},
+                {"num": "1.4.10", "yanked": false}
+            ]
+        }"#;
+        let (base, server) = serve(&[("200 OK", payload)]);
+        let backend = CratesIo {
+            api_base: base.clone(),
+            name: "cosmic-text".to_string(),
+            filter: VersionFilter::default(),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("cosmic-text"),
+                &archive_upstream("https://crates.io/api/v1/crates/cosmic-text/1.4.2/download"),
+            )
+            .expect("crates.io release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /crates/cosmic-text "));
+        assert_eq!(release.version, "1.4.10");
+        assert_eq!(
+            release.archive_url,
+            Some(format!("{base}/crates/cosmic-text/1.4.10/download"))
+        );
+    }
+
+    #[test]
+    fn test_pypi_backend_ignored_versions_fall_through() {
+        let payload = r#"{
+            "info": {"name": "foo", "version": "99.0", "home_page": ""},
+            "releases": {
+                "99.0": [{"packagetype": "sdist", "url": "https://files.example/foo-99.0.tar.gz"}],
+                "2.4.1": [{"packagetype": "sdist", "url": "https://files.example/foo-2.4.1.tar.gz"}],
+                "2.4.0": [{"packagetype": "sdist", "url": "https://files.example/foo-2.4.0.tar.gz"}]
+            }
+        }"#;
+        let (base, server) = serve(&[("200 OK", payload)]);
+        let config = Config::default();
+        let backend = PyPI {
+            api_base: base,
+            project: "foo".to_string(),
+            filter: VersionFilter::new(None, Some(r"^99\."), &config)
+                .expect("Version pattern is valid"),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&config),
+                &package("python-foo"),
+                &archive_upstream("https://files.example/foo-2.4.0.tar.gz"),
+            )
+            .expect("PyPI release should resolve");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(release.version, "2.4.1");
+        assert_eq!(
+            release.archive_url.as_deref(),
+            Some("https://files.example/foo-2.4.1.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_crates_io_backend_ignored_versions_fall_through() {
+        let payload = r#"{
+            "crate": {"name": "foo", "max_stable_version": "99.0.0", "homepage": null},
+            "versions": [
+                {"num": "99.0.0", "yanked": false},
+                {"num": "2.4.1", "yanked": false},
+                {"num": "2.4.0", "yanked": false}
+            ]
+        }"#;
+        let (base, server) = serve(&[("200 OK", payload)]);
+        let config = Config::default();
+        let backend = CratesIo {
+            api_base: base.clone(),
+            name: "foo".to_string(),
+            filter: VersionFilter::new(None, Some(r"^99\."), &config)
+                .expect("Version pattern is valid"),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&config),
+                &package("foo"),
+                &archive_upstream("https://crates.io/api/v1/crates/foo/2.4.0/download"),
+            )
+            .expect("crates.io release should resolve");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(release.version, "2.4.1");
+        assert_eq!(
+            release.archive_url,
+            Some(format!("{base}/crates/foo/2.4.1/download"))
+        );
+    }
+
+    #[test]
+    fn test_stable_only_on_pypi_and_crates_io() {
+        let pypi_payload = r#"{
+            "info": {"name": "foo", "version": "2.0.0rc1", "home_page": ""},
+            "releases": {
+                "2.0.0rc1": [{"packagetype": "sdist", "url": "https://files.example/foo-2.0.0rc1.tar.gz"}],
+                "2.0.0": [{"packagetype": "sdist", "url": "https://files.example/foo-2.0.0.tar.gz"}],
+                "1.9.0": [{"packagetype": "sdist", "url": "https://files.example/foo-1.9.0.tar.gz"}]
+            }
+        }"#;
+        let crates_payload = r#"{
+            "crate": {"name": "foo", "max_stable_version": "2.0.0", "homepage": null},
+            "versions": [
+                {"num": "2.1.0-beta.1", "yanked": false},
+                {"num": "2.0.0", "yanked": false},
+                {"num": "1.9.0", "yanked": false}
+            ]
+        }"#;
+        let (base, server) = serve(&[("200 OK", pypi_payload), ("200 OK", crates_payload)]);
+        let config = Config {
+            stable_only: true,
+            ..Config::default()
+        };
+        let filter = VersionFilter::new(None, None, &config).expect("No version patterns");
+        let client = HttpClient::new(&config);
+
+        let pypi = PyPI {
+            api_base: base.clone(),
+            project: "foo".to_string(),
+            filter: filter.clone(),
+        }
+        .latest(
+            &client,
+            &package("python-foo"),
+            &archive_upstream("https://files.example/foo-1.9.0.tar.gz"),
+        )
+        .expect("PyPI release should resolve");
+        let crates_io = CratesIo {
+            api_base: base,
+            name: "foo".to_string(),
+            filter,
+        }
+        .latest(
+            &client,
+            &package("foo"),
+            &archive_upstream("https://crates.io/api/v1/crates/foo/1.9.0/download"),
+        )
+        .expect("crates.io release should resolve");
+        server.join().expect("Test server panicked");
+
+        assert_eq!(pypi.version, "2.0.0");
+        assert_eq!(crates_io.version, "2.0.0");
+    }
+
+    #[test]
+    fn test_rss_backend_release() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>foo releases</title>
+    <link>https://foo.example.org</link>
+    <item>
+      <title><![CDATA[foo 2.5.1 released]]></title>
+      <link>https://foo.example.org/news/2.5.1</link>
+    </item>
+    <item>
+      <title>foo 2.5.0 released</title>
+    </item>
+  </channel>
+</rss>"#;
+        let (base, server) = serve(&[("200 OK", feed)]);
+        let backend = RssFeed {
+            url: format!("{base}/releases.xml"),
+        };
+
+        let release = backend
+            .latest(
+                &HttpClient::new(&Config::default()),
+                &package("foo"),
+                &archive_upstream("https://foo.example.org/foo-2.5.0.tar.xz"),
+            )
+            .expect("Feed release should resolve");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /releases.xml "));
+        assert_eq!(release.version, "2.5.1");
+    }
+
+    #[test]
+    fn test_latest_feed_version() {
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Release notes from bar</title>
+  <entry>
+    <id>tag:github.com,2008:Repository/1/v0.14.2</id>
+    <title type="html">v0.14.2</title>
+  </entry>
+</feed>"#;
+
+        assert_eq!(latest_feed_version(atom), Some("0.14.2".to_string()));
+        // The channel title isn't an entry
+        assert_eq!(
+            latest_feed_version("<rss><channel><title>foo 1.0</title></channel></rss>"),
+            None
+        );
+        assert_eq!(
+            latest_feed_version("<rss><item><title>Nightly build</title></item></rss>"),
+            None
+        );
+        assert_eq!(latest_feed_version("not a feed"), None);
+    }
+}
@@ -0,0 +1,125 @@
+use super::HttpGet;
+use crate::error::ResolveError;
+use blake2::Blake2b512;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+
+/// The digest an archive upstream is pinned with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// What stone records for archives.
+    #[default]
+    Sha256,
+    /// BLAKE2b with a 512 bit digest.
+    Blake2b,
+}
+
+/// Download the archive at `url` and return its `algorithm` checksum as lowercase hex.
+///
+/// An empty body or an HTML page (a login wall or an error page served with a 200) is
+/// refused rather than hashed.
+pub fn archive_checksum(
+    client: &dyn HttpGet,
+    url: &str,
+    algorithm: HashAlgorithm,
+) -> Result<String, ResolveError> {
+    match algorithm {
+        HashAlgorithm::Sha256 => download_digest::<Sha256>(client, url),
+        HashAlgorithm::Blake2b => download_digest::<Blake2b512>(client, url),
+    }
+}
+
+fn download_digest<D: Digest + Write>(
+    client: &dyn HttpGet,
+    url: &str,
+) -> Result<String, ResolveError> {
+    let mut hasher = D::new();
+    let download = client.download(url, &mut hasher)?;
+
+    let bad_download = |reason: &str| ResolveError::BadDownload {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    };
+    if download.len == 0 {
+        return Err(bad_download("the file is empty"));
+    }
+    if download
+        .content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.starts_with("text/html"))
+    {
+        return Err(bad_download("got an HTML page instead of an archive"));
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        repo_state::{HttpClient, tests::serve},
+    };
+    use std::fs;
+
+    fn fixture() -> String {
+        fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/archives/hello-1.0.tar"
+        ))
+        .expect("Failed to read the fixture archive")
+    }
+
+    #[test]
+    fn test_archive_checksum() {
+        let archive = fixture();
+        let (base, server) = serve(&[
+            ("200 OK\r\nContent-Type: application/x-tar", &archive),
+            ("200 OK\r\nContent-Type: application/x-tar", &archive),
+        ]);
+        let client = HttpClient::new(&Config::default());
+        let url = format!("{base}/hello-1.0.tar");
+
+        let sha256 = archive_checksum(&client, &url, HashAlgorithm::Sha256)
+            .expect("Fixture archive should download");
+        let blake2b = archive_checksum(&client, &url, HashAlgorithm::Blake2b)
+            .expect("Fixture archive should download");
+        let requests = server.join().expect("Test server panicked");
+
+        assert!(requests[0].starts_with("GET /hello-1.0.tar "));
+        assert_eq!(
+            sha256,
+            "de9e121d990070d31f8f6afbf5110de771a3fb99b7a534cdc6069967b30bcd8b"
+        );
+        assert_eq!(
+            blake2b,
+            "6aa99e85efc4a2ee9b25c22afc2009d5c9107cd538d737bae6c1795984310822b4a4cefb1bedc156776cdea5943454f3e31db292a7400d503ea768630075ed7d"
+        );
+    }
+
+    #[test]
+    fn test_bad_downloads_are_refused() {
+        let (base, server) = serve(&[
+            ("200 OK", ""),
+            (
+                "200 OK\r\nContent-Type: text/html; charset=utf-8",
+                "<html>Sign in</html>",
+            ),
+        ]);
+        let client = HttpClient::new(&Config::default());
+
+        let empty = archive_checksum(&client, &format!("{base}/empty.tar"), HashAlgorithm::Sha256);
+        let html = archive_checksum(&client, &format!("{base}/login.tar"), HashAlgorithm::Sha256);
+        server.join().expect("Test server panicked");
+
+        assert!(matches!(empty, Err(ResolveError::BadDownload { .. })));
+        assert!(matches!(html, Err(ResolveError::BadDownload { .. })));
+    }
+}
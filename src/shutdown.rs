@@ -0,0 +1,100 @@
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// A shutdown request shared between the signal handler and the update loop.
+///
+/// Once requested, the package being updated is allowed to finish but nothing new is started.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    /// A shutdown that is only ever requested through `request`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A shutdown that is requested when the process receives SIGINT or SIGTERM.
+    pub fn on_signals() -> io::Result<Self> {
+        let shutdown = Self::new();
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+
+        let handle = shutdown.clone();
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                eprintln!("Shutting down once the current packages finish");
+                handle.request();
+            }
+        });
+
+        Ok(shutdown)
+    }
+
+    /// Ask everything holding this shutdown to stop.
+    pub fn request(&self) {
+        let (requested, wake) = &*self.requested;
+        *requested.lock().expect("Shutdown flag poisoned") = true;
+        wake.notify_all();
+    }
+
+    /// Whether a shutdown has been asked for.
+    pub fn is_requested(&self) -> bool {
+        *self.requested.0.lock().expect("Shutdown flag poisoned")
+    }
+
+    /// Sleep for `timeout`, waking early if a shutdown is asked for. Returns whether it was.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (requested, wake) = &*self.requested;
+        let requested = requested.lock().expect("Shutdown flag poisoned");
+        let (requested, _) = wake
+            .wait_timeout_while(requested, timeout, |requested| !*requested)
+            .expect("Shutdown flag poisoned");
+
+        *requested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_wait_wakes_on_request() {
+        let shutdown = Shutdown::new();
+
+        let handle = shutdown.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.request();
+        });
+
+        let start = Instant::now();
+        assert!(shutdown.wait(Duration::from_secs(30)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_wait_times_out_without_request() {
+        assert!(!Shutdown::new().wait(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_sigterm_requests_shutdown() {
+        let shutdown = Shutdown::on_signals().expect("Failed to install signal handlers");
+
+        signal_hook::low_level::raise(SIGTERM).expect("Failed to raise SIGTERM");
+
+        // Reaching this point at all means the default handler didn't kill the process
+        assert!(shutdown.wait(Duration::from_secs(5)));
+    }
+}